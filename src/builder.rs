@@ -1,12 +1,59 @@
 use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::Read;
 use std::path::Path;
+use std::sync::Arc;
+use std::time::Duration;
+
+use regex::Regex;
+use rustc_serialize::json::Json;
 
 use super::context::{Context};
-use super::handler::Handler;
-use super::config::{self, Config, Route, MethodHandler};
+use super::directory::Directory;
+use super::handler::{Body, Handler};
+use super::event_stream;
+use super::fake::Fake;
+use super::fault::Fault;
+use super::http_status;
+use super::openapi::{self, Validation};
+use super::proxy::Upstream;
+use super::literal::Binary;
+use super::mime::{self, MimeTypes};
+use super::transfer::Chunk;
+use super::websocket::{self, Message, Opcode, WebSocket};
+use super::config::{self, Config, Content, Route, MethodHandler};
 
 pub fn build_context(context: &mut Context, configuration: Config) -> Result<(), String> {
-    for (path, route) in configuration.routes.iter() {
+    try!(process_routes(&configuration.routes, &configuration, context));
+
+    for (host, routes) in configuration.hosts.iter() {
+        let mut host_context = Context::new();
+        try!(process_routes(routes, &configuration, &mut host_context));
+        context.add_host(host, host_context.into_routes());
+    }
+
+    for (index, listener) in configuration.listeners.iter().enumerate() {
+        if !listener.routes.is_empty() {
+            let mut listener_context = Context::new();
+            try!(process_routes(&listener.routes, &configuration, &mut listener_context));
+            context.set_listener_routes(index, listener_context.into_routes());
+        }
+    }
+
+    if let Some(ref url) = configuration.settings.proxy {
+        context.set_proxy(try!(Upstream::parse(url)));
+    }
+    if let Some(ref dir) = configuration.settings.record {
+        context.set_recorder(dir);
+    }
+
+    process_notfound(&configuration, context)
+}
+
+fn process_routes(routes: &BTreeMap<String, Route>, configuration: &Config, context: &mut Context)
+    -> Result<(), String>
+{
+    for (path, route) in routes.iter() {
         let path = if !path.starts_with("^") {
             format!("^{}", path)
         } else {
@@ -15,15 +62,23 @@ pub fn build_context(context: &mut Context, configuration: Config) -> Result<(),
 
         match route {
             &Route::Include(ref filename) =>
-                try!(process_include(filename, path, &configuration, context)),
+                try!(process_include(filename, path, configuration, context)),
             &Route::Handler(ref route_handler) => {
-                try!(process_handler(path, route_handler, &configuration, context));
+                try!(process_handler(path, route_handler, configuration, context));
+            }
+            &Route::Directory(ref directory) => {
+                try!(process_directory(path, directory, configuration, context));
+            }
+            &Route::WebSocket(ref websocket) => {
+                try!(process_websocket(path, websocket, configuration, context));
+            }
+            &Route::Proxy(ref url) => try!(process_proxy(path, url, context)),
+            &Route::OpenApi(ref openapi) => {
+                try!(process_openapi(path, openapi, configuration, context));
             }
         }
     }
 
-    process_notfound(&configuration, context);
-
     Ok(())
 }
 
@@ -41,6 +96,16 @@ fn process_include(filename: &Path, root_path: String, configuration: &Config, c
             &Route::Handler(ref route_handler) => {
                 try!(process_handler(path, route_handler, &configuration, context));
             }
+            &Route::Directory(ref directory) => {
+                try!(process_directory(path, directory, configuration, context));
+            }
+            &Route::WebSocket(ref websocket) => {
+                try!(process_websocket(path, websocket, configuration, context));
+            }
+            &Route::Proxy(ref url) => try!(process_proxy(path, url, context)),
+            &Route::OpenApi(ref openapi) => {
+                try!(process_openapi(path, openapi, configuration, context));
+            }
         }
     }
 
@@ -60,54 +125,385 @@ fn process_handler(path: String,
     };
 
     for (method, handler_config) in route.handlers() {
-        let mut handler = Handler::new(handler_config.status);
-        handler.set_content(handler_config.content.clone());
+        let status = match handler_config.redirect {
+            Some(ref redirect) => match redirect.status {
+                301 | 302 | 303 | 307 | 308 => redirect.status,
+                status => return Err(format!("Invalid redirect status {} for {}", status, path)),
+            },
+            None => handler_config.status,
+        };
+
+        if !handler_config.representations.is_empty() &&
+           (handler_config.content.is_some() || !handler_config.chunks.is_empty()) {
+            return Err(format!("Both content and representations are set for {}", path));
+        }
 
-        if handler_config.content.is_some() {
-            let content_type = handler_config.content_type.as_ref().map(|x| &**x)
-                .unwrap_or(configuration.settings.content_type.as_ref());
+        let mut handler = Handler::new(status);
+        if !handler_config.chunks.is_empty() {
+            handler.set_content(Some(Body::Chunks {
+                chunks: try!(process_chunks(&handler_config.chunks, configuration)),
+                repeat: false,
+            }));
+        } else if let Some(ref content) = handler_config.content {
+            handler.set_content(Some(try!(process_content(content, configuration))));
+        }
 
-            handler.add_header("Content-Type".to_owned(), content_type.as_bytes().to_owned());
+        if let Some(ref fault) = handler_config.fault {
+            handler.set_fault(Some(try!(Fault::from_config(fault))));
         }
 
+        handler.set_throttle(handler_config.throttle.or(configuration.settings.throttle));
+        handler.set_compression(compression(handler_config.compress, configuration));
+        handler.set_compress_files(handler_config.compress == Some(true));
+        handler.set_chunked(handler_config.chunked);
+
         process_headers(&mut handler,
                         &handler_config.headers,
                         &configuration.settings.headers,
                         configuration.settings.headers_replace);
 
-        try!(context.add_route(&path, method.to_owned(), handler)
+        let mut variants = Vec::new();
+        for (media_type, content) in handler_config.representations.iter() {
+            let mut variant = handler.clone();
+            variant.set_content(Some(try!(process_content(content, configuration))));
+            variant.add_header("Content-Type".to_owned(), media_type.as_bytes().to_owned());
+            variant.add_header("Vary".to_owned(), b"Accept".to_vec());
+            variants.push((media_type.clone(), variant));
+        }
+
+        for (media_type, variant) in variants {
+            handler.add_variant(media_type, variant);
+        }
+
+        let content = handler_config.content.as_ref()
+            .or(handler_config.chunks.first().map(|c| &c.content));
+
+        if let Some(content) = content {
+            let content_type = default_content_type(handler_config.content_type.as_ref(),
+                                                    content,
+                                                    configuration);
+
+            handler.add_header("Content-Type".to_owned(), content_type.into_bytes());
+
+            if let &Content::EventStream(_) = content {
+                if handler.header("Cache-Control").is_none() {
+                    handler.add_header("Cache-Control".to_owned(), b"no-cache".to_vec());
+                }
+            }
+        }
+
+        let result = match handler_config.redirect {
+            Some(ref redirect) => {
+                context.add_redirect(&path, method.to_owned(), handler, redirect.location.clone())
+            }
+            None => context.add_route(&path, method.to_owned(), handler),
+        };
+        try!(result.map_err(|e| format!("Error adding route: {}", e)));
+    }
+
+    Ok(())
+}
+
+fn process_directory(path: String,
+                     directory: &config::Directory,
+                     configuration: &Config,
+                     context: &mut Context)
+                     -> Result<(), String>
+{
+    let path = format!("{}(?P<path>/.*)?$", path.trim_right_matches("$").trim_right_matches("/"));
+
+    let mut template = Handler::new(200);
+    template.set_throttle(configuration.settings.throttle);
+    template.set_compression(compression(None, configuration));
+    process_headers(&mut template,
+                    &directory.headers,
+                    &configuration.settings.headers,
+                    configuration.settings.headers_replace);
+
+    let directory = Directory::new(directory.path.clone(),
+                                   directory.index.clone(),
+                                   directory.listing,
+                                   template,
+                                   mime_types(configuration),
+                                   configuration.settings.content_type.clone());
+
+    context.add_directory(&path, directory)
+        .map_err(|e| format!("Error adding route: {}", e))
+}
+
+fn process_websocket(path: String,
+                     websocket: &config::WebSocket,
+                     configuration: &Config,
+                     context: &mut Context)
+                     -> Result<(), String>
+{
+    let path = if !path.ends_with("$") {
+        format!("{}$", path)
+    } else {
+        path
+    };
+
+    let mut replies = Vec::new();
+    for reply in websocket.replies.iter() {
+        let pattern = match reply.pattern {
+            Some(ref pattern) => Some(try!(Regex::new(pattern)
+                .map_err(|e| format!("Invalid reply pattern {}: {}", pattern, e)))),
+            None => None,
+        };
+
+        replies.push(websocket::Reply {
+            pattern: pattern,
+            // the value is checked when the configuration is validated
+            json: reply.json.as_ref().and_then(|json| Json::from_str(json).ok()),
+            messages: try!(process_messages(&reply.messages, configuration)),
+            close: match reply.close {
+                Some(ref close) => Some(try!(process_close(close))),
+                None => None,
+            },
+        });
+    }
+
+    let websocket = WebSocket {
+        messages: try!(process_messages(&websocket.messages, configuration)),
+        replies: replies,
+        close: match websocket.close {
+            Some(ref close) => Some(try!(process_close(close))),
+            None => None,
+        },
+    };
+
+    // answers requests to the route that do not ask for the upgrade
+    let status = http_status::UpgradeRequired;
+    let mut handler = Handler::new(status.code());
+    let data = format!("{} - {}", status.code(), status.description());
+    handler.set_content(Some(Body::Data(data.into_bytes())));
+    handler.add_header("Content-Type".to_owned(), b"text/plain".to_vec());
+    handler.add_header("Upgrade".to_owned(), b"websocket".to_vec());
+    handler.add_header("Sec-WebSocket-Version".to_owned(), b"13".to_vec());
+
+    context.add_websocket(&path, websocket, handler)
+        .map_err(|e| format!("Error adding route: {}", e))
+}
+
+fn process_proxy(path: String, url: &str, context: &mut Context) -> Result<(), String> {
+    let path = format!("{}(?:[/?].*)?$", path.trim_right_matches("$").trim_right_matches("/"));
+    let upstream = try!(Upstream::parse(url));
+
+    context.add_proxy(&path, upstream)
+        .map_err(|e| format!("Error adding route: {}", e))
+}
+
+fn process_openapi(path: String,
+                   openapi: &config::OpenApi,
+                   configuration: &Config,
+                   context: &mut Context)
+                   -> Result<(), String>
+{
+    let spec = Arc::new(try!(openapi::read_spec(&openapi.spec)));
+    let operations = try!(openapi::operations(&spec)
+        .map_err(|e| format!("Error reading {}: {}", openapi.spec.display(), e)));
+
+    for operation in operations {
+        // the query string is not part of the operation path
+        let route = format!("{}{}(?:\\?.*)?$", path.trim_right_matches("$").trim_right_matches("/"),
+                            operation.path);
+
+        let mut handler = Handler::new(operation.status);
+        handler.set_throttle(configuration.settings.throttle);
+        handler.set_compression(compression(None, configuration));
+
+        let mut headers = openapi.headers.clone();
+        for &(ref name, ref value) in operation.headers.iter() {
+            headers.entry(name.clone()).or_insert(value.clone());
+        }
+        process_headers(&mut handler,
+                        &headers,
+                        &configuration.settings.headers,
+                        configuration.settings.headers_replace);
+
+        let pretty = configuration.settings.json_pretty;
+        if operation.examples.len() > 1 {
+            let variants: Vec<_> = operation.examples.iter().map(|example| {
+                let mut variant = handler.clone();
+                variant.set_content(Some(Body::Data(example.body(pretty))));
+                variant.add_header("Content-Type".to_owned(), example.media_type.as_bytes().to_owned());
+                variant.add_header("Vary".to_owned(), b"Accept".to_vec());
+                (example.media_type.clone(), variant)
+            }).collect();
+
+            for (media_type, variant) in variants {
+                handler.add_variant(media_type, variant);
+            }
+        } else if let Some(example) = operation.examples.first() {
+            handler.set_content(Some(Body::Data(example.body(pretty))));
+            handler.add_header("Content-Type".to_owned(), example.media_type.as_bytes().to_owned());
+        }
+
+        if openapi.validate {
+            let re = try!(Regex::new(&route).map_err(|e| format!("Error adding route: {}", e)));
+            handler.set_validation(Some(Validation::new(spec.clone(), re, &operation,
+                                                        openapi.invalid_status)));
+        }
+
+        try!(context.add_route(&route, operation.method, handler)
             .map_err(|e| format!("Error adding route: {}", e)));
     }
 
     Ok(())
 }
 
-fn process_notfound(configuration: &Config, context: &mut Context) {
+fn process_messages(chunks: &[config::Chunk], configuration: &Config) -> Result<Vec<Message>, String> {
+    let data = try!(process_chunks(chunks, configuration));
+    Ok(chunks.iter().zip(data).map(|(chunk, data)| {
+        let opcode = match chunk.content {
+            Content::Data(_) | Content::Json(_) | Content::Yaml(_) | Content::EventStream(_) |
+            Content::Fake(_) => Opcode::Text,
+            Content::File(_) | Content::Base64(_) | Content::Hex(_) => Opcode::Binary,
+        };
+
+        Message {
+            opcode: opcode,
+            data: data.data,
+            fake: data.fake,
+            delay: data.delay,
+        }
+    }).collect())
+}
+
+fn process_close(close: &config::Close) -> Result<websocket::Close, String> {
+    if !websocket::valid_close_code(close.code) {
+        return Err(format!("Invalid close code {}", close.code));
+    }
+
+    Ok(websocket::Close {
+        code: close.code,
+        reason: close.reason.clone(),
+        delay: Duration::from_millis(close.delay),
+    })
+}
+
+fn process_notfound(configuration: &Config, context: &mut Context) -> Result<(), String> {
     match configuration.not_found {
         Some(ref not_found) => {
             let mut handler = Handler::new(404);
-            handler.set_content(not_found.content.clone());
+            if let Some(ref content) = not_found.content {
+                handler.set_content(Some(try!(process_content(content, configuration))));
+            }
+            handler.set_throttle(configuration.settings.throttle);
+            handler.set_compression(compression(None, configuration));
 
-            if not_found.content.is_some() {
-                let content_type = not_found.content_type.as_ref().map(|x| &**x)
-                    .unwrap_or(configuration.settings.content_type.as_ref());
+            if let Some(ref content) = not_found.content {
+                let content_type = default_content_type(not_found.content_type.as_ref(),
+                                                        content,
+                                                        configuration);
 
-                handler.add_header("Content-Type".to_owned(), content_type.as_bytes().to_owned());
+                handler.add_header("Content-Type".to_owned(), content_type.into_bytes());
             }
 
             for (key, val) in not_found.headers.iter() {
                 handler.add_header(key.clone(), val.as_bytes().to_owned());
             }
 
-            let content_type = not_found.content_type.clone()
-                .unwrap_or("application/json".to_owned());
-
-            handler.add_header("Content-Type".to_owned(), content_type.as_bytes().to_owned());
-
             context.set_not_found_handler(handler);
         }
         None => {}
     }
+
+    Ok(())
+}
+
+fn process_content(content: &Content, configuration: &Config) -> Result<Body, String> {
+    let body = match *content {
+        Content::Data(ref data) => Body::Data(data.as_bytes().to_owned()),
+        Content::File(ref path) => Body::File(path.clone()),
+        Content::Json(ref json) | Content::Yaml(ref json) => {
+            let json = if configuration.settings.json_pretty {
+                Json::from_str(json).map(|j| j.pretty().to_string()).unwrap_or(json.clone())
+            } else {
+                json.clone()
+            };
+            Body::Data(json.into_bytes())
+        }
+        // the data is checked when the configuration is validated
+        Content::Base64(ref text) => Body::Data(Binary::Base64.decode(text).unwrap_or_default()),
+        Content::Hex(ref text) => Body::Data(Binary::Hex.decode(text).unwrap_or_default()),
+        Content::EventStream(ref stream) => {
+            let chunks = event_stream::chunks(stream);
+            if stream.repeat && chunks.iter().all(|c| c.delay == Duration::new(0, 0)) {
+                return Err("Repeated event streams need a delay between events".to_owned());
+            }
+            Body::Chunks {
+                chunks: chunks,
+                repeat: stream.repeat,
+            }
+        }
+        Content::Fake(ref fake) => {
+            let schema = match (&fake.schema, &fake.file) {
+                // the value is checked when the configuration is validated
+                (&Some(ref schema), &None) => Json::from_str(schema).unwrap_or(Json::Null),
+                (&None, &Some(ref file)) => try!(openapi::read_spec(file)),
+                _ => return Err("Fake content needs either a schema or a file".to_owned()),
+            };
+            Body::Fake(try!(Fake::new(schema, fake.seed, configuration.settings.json_pretty)))
+        }
+    };
+    Ok(body)
+}
+
+fn process_chunks(chunks: &[config::Chunk], configuration: &Config) -> Result<Vec<Chunk>, String> {
+    chunks.iter().map(|chunk| {
+        let (data, fake) = match try!(process_content(&chunk.content, configuration)) {
+            Body::Data(data) => (data, None),
+            Body::Chunks { chunks, .. } => (chunks.into_iter().flat_map(|c| c.data).collect(), None),
+            // generated again for each response or message
+            Body::Fake(fake) => (Vec::new(), Some(fake)),
+            Body::File(path) => {
+                let mut data = Vec::new();
+                try!(File::open(&path)
+                    .and_then(|mut file| file.read_to_end(&mut data))
+                    .map_err(|e| format!("Error reading chunk {}: {}", path.display(), e)));
+                (data, None)
+            }
+        };
+
+        Ok(Chunk {
+            data: data,
+            fake: fake,
+            delay: Duration::from_millis(chunk.delay),
+        })
+    }).collect()
+}
+
+fn default_content_type(content_type: Option<&String>, content: &Content, configuration: &Config)
+    -> String
+{
+    match (content_type, content) {
+        (Some(content_type), _) => content_type.clone(),
+        (None, &Content::File(ref path)) => {
+            mime_types(configuration).content_type(path)
+                .unwrap_or_else(|| configuration.settings.content_type.clone())
+        }
+        (None, &Content::Json(_)) | (None, &Content::Yaml(_)) | (None, &Content::Fake(_)) => {
+            "application/json".to_owned()
+        }
+        (None, &Content::Base64(_)) | (None, &Content::Hex(_)) => {
+            mime::DEFAULT_MIME_TYPE.to_owned()
+        }
+        (None, &Content::EventStream(_)) => event_stream::CONTENT_TYPE.to_owned(),
+        (None, _) => configuration.settings.content_type.clone(),
+    }
+}
+
+fn compression(compress: Option<bool>, configuration: &Config) -> Option<u64> {
+    if compress.unwrap_or(configuration.settings.compress) {
+        Some(configuration.settings.compress_min_size)
+    } else {
+        None
+    }
+}
+
+fn mime_types(configuration: &Config) -> MimeTypes {
+    MimeTypes::new(&configuration.settings.mime_types, &configuration.settings.charset)
 }
 
 fn process_headers(handler: &mut Handler,
@@ -0,0 +1,156 @@
+use std::collections::hash_map::DefaultHasher;
+use std::fs::Metadata;
+use std::hash::{Hash, Hasher};
+use std::time::UNIX_EPOCH;
+
+use chrono::{TimeZone, UTC};
+use rotor_http::server::Response;
+
+use super::encoding::Encoding;
+use super::request::Request;
+
+const HTTP_DATE: &'static str = "%a, %d %b %Y %H:%M:%S GMT";
+
+#[derive(Debug, PartialEq)]
+pub enum Precondition {
+    Passed,
+    NotModified,
+    Failed,
+}
+
+#[derive(Debug)]
+pub struct Validators {
+    pub etag: String,
+    pub last_modified: Option<i64>,
+}
+
+impl Validators {
+    pub fn for_data(data: &[u8]) -> Self {
+        let mut hasher = DefaultHasher::new();
+        data.hash(&mut hasher);
+        Validators {
+            etag: format!("\"{:016x}\"", hasher.finish()),
+            last_modified: None,
+        }
+    }
+
+    pub fn for_file(metadata: &Metadata) -> Self {
+        let last_modified = metadata.modified().ok()
+            .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+            .map(|d| d.as_secs() as i64);
+
+        let mut hasher = DefaultHasher::new();
+        metadata.len().hash(&mut hasher);
+        last_modified.hash(&mut hasher);
+        Validators {
+            etag: format!("\"{:016x}\"", hasher.finish()),
+            last_modified: last_modified,
+        }
+    }
+
+    pub fn encoded(self, encoding: Encoding) -> Self {
+        Validators {
+            etag: format!("{}-{}\"", self.etag.trim_right_matches('"'), encoding.name()),
+            last_modified: self.last_modified,
+        }
+    }
+
+    pub fn add_headers(&self, res: &mut Response) {
+        res.add_header("ETag", self.etag.as_bytes()).unwrap();
+        if let Some(last_modified) = self.last_modified {
+            res.add_header("Last-Modified", format_date(last_modified).as_bytes()).unwrap();
+        }
+    }
+
+    pub fn evaluate(&self, req: &Request) -> Precondition {
+        if let Some(if_match) = req.header_str("If-Match") {
+            if !etag_matches(if_match, &self.etag, false) {
+                return Precondition::Failed;
+            }
+        } else if let Some(since) = req.header_str("If-Unmodified-Since").and_then(parse_date) {
+            if self.last_modified.map(|m| m > since).unwrap_or(false) {
+                return Precondition::Failed;
+            }
+        }
+
+        let safe = req.method == "GET" || req.method == "HEAD";
+        if let Some(if_none_match) = req.header_str("If-None-Match") {
+            if etag_matches(if_none_match, &self.etag, true) {
+                return if safe { Precondition::NotModified } else { Precondition::Failed };
+            }
+        } else if let Some(since) = req.header_str("If-Modified-Since").and_then(parse_date) {
+            if safe && self.last_modified.map(|m| m <= since).unwrap_or(false) {
+                return Precondition::NotModified;
+            }
+        }
+
+        Precondition::Passed
+    }
+
+    pub fn range_applies(&self, req: &Request) -> bool {
+        match req.header_str("If-Range") {
+            Some(value) if value.starts_with('"') || value.starts_with("W/") => {
+                etag_matches(value, &self.etag, false)
+            }
+            Some(value) => match (parse_date(value), self.last_modified) {
+                (Some(date), Some(last_modified)) => date == last_modified,
+                _ => false,
+            },
+            None => true,
+        }
+    }
+}
+
+fn etag_matches(header: &str, etag: &str, weak: bool) -> bool {
+    header.split(',').map(|t| t.trim()).any(|tag| {
+        if tag == "*" {
+            return true;
+        }
+        if tag.starts_with("W/") {
+            weak && &tag[2..] == etag
+        } else {
+            tag == etag
+        }
+    })
+}
+
+pub fn format_date(timestamp: i64) -> String {
+    UTC.timestamp(timestamp, 0).format(HTTP_DATE).to_string()
+}
+
+pub fn parse_date(value: &str) -> Option<i64> {
+    UTC.datetime_from_str(value, HTTP_DATE).ok().map(|d| d.timestamp())
+}
+
+#[cfg(test)]
+mod tests {
+    use request::with_header;
+    use super::{Precondition, Validators};
+
+    #[test]
+    fn evaluate_preconditions() {
+        let validators = Validators {
+            etag: "\"abc\"".to_owned(),
+            last_modified: Some(784111777),
+        };
+        let date = "Sun, 06 Nov 1994 08:49:37 GMT";
+        let earlier = "Sat, 05 Nov 1994 08:49:37 GMT";
+
+        assert_eq!(validators.evaluate(&with_header("GET", "If-None-Match", "\"abc\"")),
+                   Precondition::NotModified);
+        assert_eq!(validators.evaluate(&with_header("GET", "If-None-Match", "W/\"abc\"")),
+                   Precondition::NotModified);
+        assert_eq!(validators.evaluate(&with_header("PUT", "If-None-Match", "*")),
+                   Precondition::Failed);
+        assert_eq!(validators.evaluate(&with_header("GET", "If-None-Match", "\"xyz\"")),
+                   Precondition::Passed);
+        assert_eq!(validators.evaluate(&with_header("GET", "If-Modified-Since", date)),
+                   Precondition::NotModified);
+        assert_eq!(validators.evaluate(&with_header("GET", "If-Modified-Since", earlier)),
+                   Precondition::Passed);
+        assert_eq!(validators.evaluate(&with_header("PUT", "If-Match", "W/\"abc\"")),
+                   Precondition::Failed);
+        assert_eq!(validators.evaluate(&with_header("PUT", "If-Unmodified-Since", earlier)),
+                   Precondition::Failed);
+    }
+}
@@ -4,20 +4,92 @@ use std::path::{Path, PathBuf};
 use quire;
 use quire::validate as V;
 
+use super::literal;
+
 const DEFAULT_CONTENT_TYPE: &'static str = "application/json";
+const DEFAULT_CHARSET: &'static str = "utf-8";
+const DEFAULT_COMPRESS_MIN_SIZE: i64 = 1024;
+const DEFAULT_EVENT_INTERVAL: i64 = 1000;
+const DEFAULT_CLOSE_CODE: i64 = 1000;
+const DEFAULT_REDIRECT_STATUS: i64 = 302;
+const DEFAULT_INVALID_STATUS: i64 = 400;
 
 #[derive(RustcDecodable, Clone, Debug)]
 pub enum Content {
     Data(String),
     File(PathBuf),
+    Json(String),
+    Yaml(String),
+    Base64(String),
+    Hex(String),
+    EventStream(EventStream),
+    Fake(Fake),
+}
+
+#[derive(RustcDecodable, Clone, Debug)]
+pub struct Event {
+    pub event: Option<String>,
+    pub data: Option<String>,
+    pub id: Option<String>,
+    pub retry: Option<u64>,
+    pub delay: Option<u64>,
+}
+
+#[derive(RustcDecodable, Clone, Debug)]
+pub struct EventStream {
+    pub events: Vec<Event>,
+    pub interval: u64,
+    pub repeat: bool,
+}
+
+#[derive(RustcDecodable, Clone, Debug)]
+pub struct Fake {
+    pub schema: Option<String>,
+    pub file: Option<PathBuf>,
+    pub seed: Option<usize>,
+}
+
+#[derive(RustcDecodable, Clone, Debug)]
+pub enum FaultKind {
+    Reset,
+    Empty,
+    MalformedChunk,
+    Truncated,
+    Garbage(usize),
+}
+
+#[derive(RustcDecodable, Debug)]
+pub struct Fault {
+    pub kind: FaultKind,
+    pub probability: f64,
+    pub seed: Option<usize>,
+}
+
+#[derive(RustcDecodable, Debug)]
+pub struct Chunk {
+    pub content: Content,
+    pub delay: u64,
+}
+
+#[derive(RustcDecodable, Debug)]
+pub struct Redirect {
+    pub location: String,
+    pub status: u16,
 }
 
 #[derive(RustcDecodable, Debug)]
 pub struct Handler {
     pub status: u16,
+    pub redirect: Option<Redirect>,
     pub content_type: Option<String>,
     pub headers: BTreeMap<String, String>,
     pub content: Option<Content>,
+    pub representations: BTreeMap<String, Content>,
+    pub fault: Option<Fault>,
+    pub throttle: Option<u64>,
+    pub compress: Option<bool>,
+    pub chunked: bool,
+    pub chunks: Vec<Chunk>,
 }
 
 #[derive(RustcDecodable, Debug)]
@@ -61,10 +133,52 @@ impl MethodHandler {
     }
 }
 
+#[derive(RustcDecodable, Debug)]
+pub struct Directory {
+    pub path: PathBuf,
+    pub index: String,
+    pub listing: bool,
+    pub headers: BTreeMap<String, String>,
+}
+
+#[derive(RustcDecodable, Debug)]
+pub struct Close {
+    pub code: u16,
+    pub reason: String,
+    pub delay: u64,
+}
+
+#[derive(RustcDecodable, Debug)]
+pub struct Reply {
+    pub pattern: Option<String>,
+    pub json: Option<String>,
+    pub messages: Vec<Chunk>,
+    pub close: Option<Close>,
+}
+
+#[derive(RustcDecodable, Debug)]
+pub struct WebSocket {
+    pub messages: Vec<Chunk>,
+    pub replies: Vec<Reply>,
+    pub close: Option<Close>,
+}
+
+#[derive(RustcDecodable, Debug)]
+pub struct OpenApi {
+    pub spec: PathBuf,
+    pub headers: BTreeMap<String, String>,
+    pub validate: bool,
+    pub invalid_status: u16,
+}
+
 #[derive(RustcDecodable, Debug)]
 pub enum Route {
     Include(PathBuf),
     Handler(MethodHandler),
+    Directory(Directory),
+    WebSocket(WebSocket),
+    Proxy(String),
+    OpenApi(OpenApi),
 }
 
 #[derive(RustcDecodable, Debug)]
@@ -74,17 +188,43 @@ pub struct NotFound {
     pub content: Option<Content>,
 }
 
+/// A self-signed certificate is used when `cert` and `key` are missing
+#[derive(RustcDecodable, Clone, Debug)]
+pub struct Tls {
+    pub cert: Option<PathBuf>,
+    pub key: Option<PathBuf>,
+    pub client_ca: Option<PathBuf>,
+}
+
 #[derive(RustcDecodable, Debug)]
 pub struct Settings {
     pub address: Option<String>,
+    pub tls: Option<Tls>,
     pub content_type: String,
     pub headers: BTreeMap<String, String>,
     pub headers_replace: bool,
+    pub throttle: Option<u64>,
+    pub mime_types: BTreeMap<String, String>,
+    pub charset: String,
+    pub compress: bool,
+    pub compress_min_size: u64,
+    pub json_pretty: bool,
+    pub proxy: Option<String>,
+    pub record: Option<PathBuf>,
+}
+
+#[derive(RustcDecodable, Debug)]
+pub struct Listener {
+    pub address: String,
+    pub tls: Option<Tls>,
+    pub routes: BTreeMap<String, Route>,
 }
 
 #[derive(RustcDecodable, Debug)]
 pub struct Config {
     pub routes: BTreeMap<String, Route>,
+    pub hosts: BTreeMap<String, BTreeMap<String, Route>>,
+    pub listeners: Vec<Listener>,
     pub not_found: Option<NotFound>,
     pub settings: Settings,
 }
@@ -93,12 +233,28 @@ macro_rules! handler {
     () => {
         V::Structure::new()
             .member("status", V::Numeric::new().optional().default(200))
+            .member("redirect", V::Structure::new()
+                .optional()
+                .member("location", V::Scalar::new())
+                .member("status", V::Numeric::new().optional().default(DEFAULT_REDIRECT_STATUS)))
             .member("content_type", V::Scalar::new().optional())
             .member("headers", V::Mapping::new(V::Scalar::new(), V::Scalar::new()))
-            .member("content", V::Enum::new()
+            .member("content", content().optional())
+            .member("representations", V::Mapping::new(V::Scalar::new(), content()))
+            .member("fault", V::Structure::new()
                 .optional()
-                .option("Data", V::Scalar::new())
-                .option("File", V::Scalar::new()))
+                .member("kind", V::Enum::new()
+                    .option("Reset", V::Nothing)
+                    .option("Empty", V::Nothing)
+                    .option("MalformedChunk", V::Nothing)
+                    .option("Truncated", V::Nothing)
+                    .option("Garbage", V::Numeric::new().min(1)))
+                .member("probability", V::Scalar::new().optional().default(1))
+                .member("seed", V::Numeric::new().optional().min(0)))
+            .member("throttle", V::Numeric::new().optional().min(0))
+            .member("compress", V::Scalar::new().optional())
+            .member("chunked", V::Scalar::new().optional().default(false))
+            .member("chunks", V::Sequence::new(chunk()))
     }
 }
 
@@ -110,23 +266,82 @@ pub fn read_config_include(filename: &Path) -> Result<BTreeMap<String, Route>, S
     quire::parse_config(filename, &validator_include(), Default::default())
 }
 
+fn content<'a>() -> V::Enum<'a> {
+    V::Enum::new()
+        .option("Data", V::Scalar::new())
+        .option("File", V::Scalar::new())
+        .option("Json", literal::JsonValue::new())
+        .option("Yaml", literal::YamlText)
+        .option("Base64", literal::Binary::Base64)
+        .option("Hex", literal::Binary::Hex)
+        .option("EventStream", V::Structure::new()
+            .member("events", V::Sequence::new(V::Structure::new()
+                .member("event", V::Scalar::new().optional())
+                .member("data", V::Scalar::new().optional())
+                .member("id", V::Scalar::new().optional())
+                .member("retry", V::Numeric::new().optional().min(0))
+                .member("delay", V::Numeric::new().optional().min(0))))
+            .member("interval", V::Numeric::new().optional().min(0)
+                .default(DEFAULT_EVENT_INTERVAL))
+            .member("repeat", V::Scalar::new().optional().default(false)))
+        .option("Fake", V::Structure::new()
+            .member("schema", literal::JsonValue::new().optional())
+            .member("file", V::Scalar::new().optional())
+            .member("seed", V::Numeric::new().optional().min(0)))
+}
+
+fn chunk<'a>() -> V::Structure<'a> {
+    V::Structure::new()
+        .member("content", content())
+        .member("delay", V::Numeric::new().optional().min(0).default(0))
+}
+
+fn close<'a>() -> V::Structure<'a> {
+    V::Structure::new()
+        .member("code", V::Numeric::new().optional().min(1000).max(4999)
+            .default(DEFAULT_CLOSE_CODE))
+        .member("reason", V::Scalar::new().optional().default(""))
+        .member("delay", V::Numeric::new().optional().min(0).default(0))
+}
+
+fn tls<'a>() -> V::Structure<'a> {
+    V::Structure::new()
+        .member("cert", V::Scalar::new().optional())
+        .member("key", V::Scalar::new().optional())
+        .member("client_ca", V::Scalar::new().optional())
+}
+
 fn validator<'a>() -> V::Structure<'a> {
     let not_found = V::Structure::new()
         .member("content_type", V::Scalar::new().optional())
         .member("headers", V::Mapping::new(V::Scalar::new(), V::Scalar::new()))
-        .member("content", V::Enum::new()
-            .optional()
-            .option("Data", V::Scalar::new())
-            .option("File", V::Scalar::new()));
+        .member("content", content().optional());
 
     let settings = V::Structure::new()
         .member("address", V::Scalar::new().optional())
+        .member("tls", tls().optional())
         .member("content_type", V::Scalar::new().optional().default(DEFAULT_CONTENT_TYPE))
         .member("headers", V::Mapping::new(V::Scalar::new(), V::Scalar::new()))
-        .member("headers_replace", V::Scalar::new().optional().default(false));
+        .member("headers_replace", V::Scalar::new().optional().default(false))
+        .member("throttle", V::Numeric::new().optional().min(0))
+        .member("mime_types", V::Mapping::new(V::Scalar::new(), V::Scalar::new()))
+        .member("charset", V::Scalar::new().optional().default(DEFAULT_CHARSET))
+        .member("compress", V::Scalar::new().optional().default(true))
+        .member("compress_min_size", V::Numeric::new().optional().min(0)
+            .default(DEFAULT_COMPRESS_MIN_SIZE))
+        .member("json_pretty", V::Scalar::new().optional().default(false))
+        .member("proxy", V::Scalar::new().optional())
+        .member("record", V::Scalar::new().optional());
+
+    let listener = V::Structure::new()
+        .member("address", V::Scalar::new())
+        .member("tls", tls().optional())
+        .member("routes", route_collection());
 
     V::Structure::new()
         .member("routes", route_collection())
+        .member("hosts", V::Mapping::new(V::Scalar::new(), route_collection()))
+        .member("listeners", V::Sequence::new(listener))
         .member("not_found", not_found)
         .member("settings", settings)
 }
@@ -148,7 +363,27 @@ fn route_collection<'a>() -> V::Mapping<'a> {
             .member("TRACE", handler!().optional())
             .member("OPTIONS", handler!().optional())
             .member("CONNECT", handler!().optional())
-            .member("PATCH", handler!().optional()));
+            .member("PATCH", handler!().optional()))
+        .option("Directory", V::Structure::new()
+            .member("path", V::Scalar::new())
+            .member("index", V::Scalar::new().optional().default("index.html"))
+            .member("listing", V::Scalar::new().optional().default(false))
+            .member("headers", V::Mapping::new(V::Scalar::new(), V::Scalar::new())))
+        .option("WebSocket", V::Structure::new()
+            .member("messages", V::Sequence::new(chunk()))
+            .member("replies", V::Sequence::new(V::Structure::new()
+                .member("pattern", V::Scalar::new().optional())
+                .member("json", literal::JsonValue::new().optional())
+                .member("messages", V::Sequence::new(chunk()))
+                .member("close", close().optional())))
+            .member("close", close().optional()))
+        .option("Proxy", V::Scalar::new())
+        .option("OpenApi", V::Structure::new()
+            .member("spec", V::Scalar::new())
+            .member("headers", V::Mapping::new(V::Scalar::new(), V::Scalar::new()))
+            .member("validate", V::Scalar::new().optional().default(false))
+            .member("invalid_status", V::Numeric::new().optional().min(400).max(599)
+                .default(DEFAULT_INVALID_STATUS)));
 
     V::Mapping::new(V::Scalar::new(), route)
 }
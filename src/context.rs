@@ -1,3 +1,4 @@
+use std::collections::BTreeMap;
 use std::path::{Path, PathBuf};
 use std::sync::Arc;
 
@@ -5,16 +6,32 @@ use regex::{self, Regex};
 
 use super::builder;
 use super::config;
+use super::directory::Directory;
 use super::handler::Handler;
+use super::proxy::{Upstream, UpstreamResponse};
+use super::record::Recorder;
+use super::request::Request;
+use super::websocket::WebSocket;
 
 pub const DEFAULT_ADDR: &'static str = "127.0.0.1:7000";
 
+const PROXY_METHODS: [&'static str; 7] = ["GET", "HEAD", "POST", "PUT", "DELETE", "OPTIONS", "PATCH"];
+
+
+#[derive(Debug)]
+enum Target {
+    Handler(Arc<Handler>),
+    Redirect(Arc<Handler>, String),
+    Directory(Arc<Directory>),
+    WebSocket(Arc<WebSocket>, Arc<Handler>),
+    Proxy(Arc<Upstream>),
+}
 
 #[derive(Debug)]
 pub struct Route {
     re: Regex,
     method: String,
-    handler: Arc<Handler>,
+    target: Target,
 }
 
 impl Route {
@@ -22,7 +39,7 @@ impl Route {
         Route {
             re: re,
             method: method,
-            handler: Arc::new(handler),
+            target: Target::Handler(Arc::new(handler)),
         }
     }
 
@@ -30,40 +47,124 @@ impl Route {
         self.method == method && self.re.is_match(path)
     }
 
-    pub fn handler(&self) -> Arc<Handler> {
-        self.handler.clone()
+    pub fn handler(&self, path: &str) -> Option<Arc<Handler>> {
+        match self.target {
+            Target::Handler(ref handler) => Some(handler.clone()),
+            Target::Redirect(ref handler, ref location) => {
+                let location = match self.re.captures(path) {
+                    Some(captures) => captures.expand(location),
+                    None => location.clone(),
+                };
+                let mut handler = (**handler).clone();
+                handler.add_header("Location".to_owned(), location.into_bytes());
+                Some(Arc::new(handler))
+            }
+            Target::Directory(ref directory) => {
+                let rel_path = self.re.captures(path)
+                    .and_then(|c| c.name("path"))
+                    .unwrap_or("/");
+                directory.handler(path, rel_path).map(Arc::new)
+            }
+            Target::WebSocket(_, ref handler) => Some(handler.clone()),
+            Target::Proxy(_) => None,
+        }
+    }
+
+    pub fn proxy(&self) -> Option<Arc<Upstream>> {
+        match self.target {
+            Target::Proxy(ref upstream) => Some(upstream.clone()),
+            _ => None,
+        }
+    }
+
+    pub fn websocket(&self, path: &str) -> Option<Arc<WebSocket>> {
+        match self.target {
+            Target::WebSocket(ref websocket, _) if self.re.is_match(path) => {
+                Some(websocket.clone())
+            }
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct Listener {
+    address: String,
+    tls: Option<config::Tls>,
+    routes: Option<Vec<Route>>,
+}
+
+impl Listener {
+    pub fn new(address: String, tls: Option<config::Tls>) -> Self {
+        Listener {
+            address: address,
+            tls: tls,
+            routes: None,
+        }
+    }
+
+    pub fn address(&self) -> &str {
+        &self.address
+    }
+
+    pub fn tls(&self) -> Option<&config::Tls> {
+        self.tls.as_ref()
     }
 }
 
 #[derive(Debug)]
 pub struct Context {
     routes: Vec<Route>,
+    hosts: BTreeMap<String, Vec<Route>>,
     not_found_handler: Option<Handler>,
+    proxy: Option<Arc<Upstream>>,
+    recorder: Option<Recorder>,
     config_file: Option<PathBuf>,
     autoreload: bool,
-    address: String,
+    listeners: Vec<Listener>,
 }
 
 impl Context {
     pub fn new() -> Self {
         Context {
             routes: Vec::new(),
+            hosts: BTreeMap::new(),
             not_found_handler: None,
+            proxy: None,
+            recorder: None,
             config_file: None,
             autoreload: false,
-            address: String::new(),
+            listeners: vec![Listener::new(DEFAULT_ADDR.to_owned(), None)],
         }
     }
 
     pub fn from_config_file(config_file: &Path, autoreload: bool) -> Result<Self, String> {
         let config = try!(config::read_config(config_file));
 
+        if !config.listeners.is_empty() &&
+           (config.settings.address.is_some() || config.settings.tls.is_some()) {
+            return Err("The address and tls settings cannot be used with listeners, \
+                        set them on each listener instead".to_owned());
+        }
+
+        let listeners = if config.listeners.is_empty() {
+            let address = config.settings.address.clone().unwrap_or(DEFAULT_ADDR.to_owned());
+            vec![Listener::new(address, config.settings.tls.clone())]
+        } else {
+            config.listeners.iter()
+                .map(|l| Listener::new(l.address.clone(), l.tls.clone()))
+                .collect()
+        };
+
         let mut context = Context {
             routes: Vec::new(),
+            hosts: BTreeMap::new(),
             not_found_handler: None,
+            proxy: None,
+            recorder: None,
             config_file: Some(config_file.to_path_buf()),
             autoreload: autoreload,
-            address: config.settings.address.clone().unwrap_or(DEFAULT_ADDR.to_owned()),
+            listeners: listeners,
         };
 
         try!(builder::build_context(&mut context, config));
@@ -77,15 +178,57 @@ impl Context {
            None => return Err("Cannot rebuild context without configuration file.".to_owned()),
        };
        self.routes.clear();
+       self.hosts.clear();
+       for listener in self.listeners.iter_mut() {
+           listener.routes.take();
+       }
        self.not_found_handler.take();
+       self.proxy.take();
+       let recorder = self.recorder.take();
        let c = try!(config::read_config(config_file.as_path()));
-       builder::build_context(self, c)
+       try!(builder::build_context(self, c));
+       // keep the responses recorded so far if the directory did not change
+       if let Some(recorder) = recorder {
+           if self.recorder.as_ref().map_or(false, |r| r.dir() == recorder.dir()) {
+               self.recorder = Some(recorder);
+           }
+       }
+       Ok(())
     }
 
     pub fn routes(&self) -> &Vec<Route> {
         &self.routes
     }
 
+    pub fn into_routes(self) -> Vec<Route> {
+        self.routes
+    }
+
+    pub fn listener_routes(&self, index: usize) -> &Vec<Route> {
+        match self.listeners.get(index).and_then(|l| l.routes.as_ref()) {
+            Some(routes) => routes,
+            None => &self.routes,
+        }
+    }
+
+    pub fn routes_for(&self, index: usize, host: Option<&str>) -> &Vec<Route> {
+        let host = host.map(|h| host_name(h).to_ascii_lowercase());
+        match host.and_then(|h| self.hosts.get(&h)) {
+            Some(routes) => routes,
+            None => self.listener_routes(index),
+        }
+    }
+
+    pub fn add_host(&mut self, host: &str, routes: Vec<Route>) {
+        self.hosts.insert(host_name(host).to_ascii_lowercase(), routes);
+    }
+
+    pub fn set_listener_routes(&mut self, index: usize, routes: Vec<Route>) {
+        if let Some(listener) = self.listeners.get_mut(index) {
+            listener.routes = Some(routes);
+        }
+    }
+
     pub fn add_route(&mut self, path: &str, method: String, handler: Handler)
         -> Result<(), regex::Error>
     {
@@ -94,6 +237,81 @@ impl Context {
         Ok(())
     }
 
+    pub fn add_redirect(&mut self, path: &str, method: String, handler: Handler, location: String)
+        -> Result<(), regex::Error>
+    {
+        let re = try!(Regex::new(path));
+        self.routes.push(Route {
+            re: re,
+            method: method,
+            target: Target::Redirect(Arc::new(handler), location),
+        });
+        Ok(())
+    }
+
+    pub fn add_directory(&mut self, path: &str, directory: Directory)
+        -> Result<(), regex::Error>
+    {
+        let re = try!(Regex::new(path));
+        let directory = Arc::new(directory);
+        for method in ["GET", "HEAD"].iter() {
+            self.routes.push(Route {
+                re: re.clone(),
+                method: method.to_string(),
+                target: Target::Directory(directory.clone()),
+            });
+        }
+        Ok(())
+    }
+
+    pub fn add_websocket(&mut self, path: &str, websocket: WebSocket, handler: Handler)
+        -> Result<(), regex::Error>
+    {
+        let re = try!(Regex::new(path));
+        self.routes.push(Route {
+            re: re,
+            method: "GET".to_owned(),
+            target: Target::WebSocket(Arc::new(websocket), Arc::new(handler)),
+        });
+        Ok(())
+    }
+
+    pub fn add_proxy(&mut self, path: &str, upstream: Upstream) -> Result<(), regex::Error> {
+        let re = try!(Regex::new(path));
+        let upstream = Arc::new(upstream);
+        for method in PROXY_METHODS.iter() {
+            self.routes.push(Route {
+                re: re.clone(),
+                method: method.to_string(),
+                target: Target::Proxy(upstream.clone()),
+            });
+        }
+        Ok(())
+    }
+
+    pub fn proxy(&self) -> Option<&Arc<Upstream>> {
+        self.proxy.as_ref()
+    }
+
+    pub fn set_proxy(&mut self, upstream: Upstream) {
+        self.proxy = Some(Arc::new(upstream));
+    }
+
+    pub fn set_recorder(&mut self, dir: &Path) {
+        self.recorder = Some(Recorder::new(dir.to_path_buf()));
+    }
+
+    pub fn record(&mut self, req: &Request, res: &UpstreamResponse) -> Result<(), String> {
+        let recorder = match self.recorder {
+            Some(ref mut recorder) if req.method != "HEAD" => recorder,
+            _ => return Ok(()),
+        };
+        if try!(recorder.record(&req.method, &req.path, res.status, &res.headers, &res.body)) {
+            try!(recorder.write());
+        }
+        Ok(())
+    }
+
     pub fn not_found_handler(&self) -> Option<&Handler> {
         self.not_found_handler.as_ref()
     }
@@ -106,7 +324,56 @@ impl Context {
         self.autoreload
     }
 
+    pub fn listeners(&self) -> &[Listener] {
+        &self.listeners
+    }
+
     pub fn address(&self) -> &str {
-        &self.address
+        self.listeners.first().map_or(DEFAULT_ADDR, |l| l.address())
+    }
+
+    pub fn set_address(&mut self, address: &str) {
+        if let Some(listener) = self.listeners.first_mut() {
+            listener.address = address.to_owned();
+        }
+    }
+}
+
+fn host_name(host: &str) -> &str {
+    match host.rfind(':') {
+        // the colons of IPv6 addresses are enclosed in brackets
+        Some(i) if !host[i..].contains(']') => &host[..i],
+        _ => host,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use handler::Handler;
+    use super::{host_name, Context, DEFAULT_ADDR};
+
+    #[test]
+    fn host_without_port() {
+        assert_eq!(host_name("api.local"), "api.local");
+        assert_eq!(host_name("api.local:8080"), "api.local");
+        assert_eq!(host_name("[::1]:8080"), "[::1]");
+        assert_eq!(host_name("[::1]"), "[::1]");
+    }
+
+    #[test]
+    fn host_key_without_port() {
+        let mut context = Context::new();
+        context.add_route("/", "GET".to_owned(), Handler::new(200)).unwrap();
+        context.add_host("API.local:8080", Vec::new());
+        assert!(context.hosts.contains_key("api.local"));
+        assert!(context.routes_for(0, Some("api.local:9000")).is_empty());
+    }
+
+    #[test]
+    fn address_of_first_listener() {
+        let mut context = Context::new();
+        assert_eq!(context.address(), DEFAULT_ADDR);
+        context.set_address("127.0.0.1:8000");
+        assert_eq!(context.address(), "127.0.0.1:8000");
     }
 }
@@ -0,0 +1,176 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use super::handler::{Body, Handler};
+use super::mime::MimeTypes;
+
+#[derive(Debug)]
+pub struct Directory {
+    root: PathBuf,
+    index: String,
+    listing: bool,
+    template: Handler,
+    mime_types: MimeTypes,
+    content_type: String,
+}
+
+impl Directory {
+    pub fn new(root: PathBuf,
+               index: String,
+               listing: bool,
+               template: Handler,
+               mime_types: MimeTypes,
+               content_type: String)
+               -> Self
+    {
+        Directory {
+            root: root,
+            index: index,
+            listing: listing,
+            template: template,
+            mime_types: mime_types,
+            content_type: content_type,
+        }
+    }
+
+    pub fn handler(&self, full_path: &str, path: &str) -> Option<Handler> {
+        let full_path = full_path.split('?').next().unwrap_or("");
+        let path = match percent_decode(path.split('?').next().unwrap_or("")) {
+            Some(path) => path,
+            None => return Some(Handler::new(400)),
+        };
+
+        if path.split('/').any(|c| c == "..") {
+            return Some(Handler::new(403));
+        }
+
+        let mut file_path = self.root.clone();
+        for component in path.split('/').filter(|c| !c.is_empty() && *c != ".") {
+            file_path.push(component);
+        }
+
+        if file_path.is_dir() {
+            if !full_path.ends_with("/") {
+                let location = format!("{}/", full_path);
+                return Some(Handler::new(301)
+                    .with_header("Location".to_owned(), location.into_bytes()));
+            }
+
+            let index = file_path.join(&self.index);
+            if index.is_file() {
+                Some(self.file_handler(index))
+            } else if self.listing {
+                self.listing_handler(&file_path, full_path)
+            } else {
+                None
+            }
+        } else if file_path.is_file() {
+            Some(self.file_handler(file_path))
+        } else {
+            None
+        }
+    }
+
+    fn file_handler(&self, path: PathBuf) -> Handler {
+        let mut handler = self.template.clone();
+        if handler.header("Content-Type").is_none() {
+            let content_type = self.mime_types.content_type(&path)
+                .unwrap_or_else(|| self.content_type.clone());
+            handler.add_header("Content-Type".to_owned(), content_type.into_bytes());
+        }
+        handler.set_content(Some(Body::File(path)));
+        handler
+    }
+
+    fn listing_handler(&self, path: &Path, full_path: &str) -> Option<Handler> {
+        let mut entries = match fs::read_dir(path) {
+            Ok(entries) => entries
+                .filter_map(|e| e.ok())
+                .map(|e| {
+                    let name = e.file_name().to_string_lossy().into_owned();
+                    if e.path().is_dir() { format!("{}/", name) } else { name }
+                })
+                .collect::<Vec<_>>(),
+            Err(e) => {
+                error!("Error listing {}: {}", path.display(), e);
+                return None;
+            }
+        };
+        entries.sort();
+
+        let title = escape_html(full_path);
+        let mut body = format!("<!DOCTYPE html>\n<html>\n<head><title>Index of {0}</title></head>\n\
+                                <body>\n<h1>Index of {0}</h1>\n<ul>\n", title);
+        if full_path != "/" {
+            body.push_str("<li><a href=\"../\">../</a></li>\n");
+        }
+        for entry in entries.iter() {
+            body.push_str(&format!("<li><a href=\"{}\">{}</a></li>\n",
+                                   percent_encode(entry), escape_html(entry)));
+        }
+        body.push_str("</ul>\n</body>\n</html>\n");
+
+        let mut handler = self.template.clone();
+        handler.add_header("Content-Type".to_owned(), b"text/html; charset=utf-8".to_vec());
+        handler.set_content(Some(Body::Data(body.into_bytes())));
+        Some(handler)
+    }
+}
+
+pub fn percent_decode(path: &str) -> Option<String> {
+    let bytes = path.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'%' {
+            if i + 2 >= bytes.len() {
+                return None;
+            }
+            let hex = match ::std::str::from_utf8(&bytes[i + 1..i + 3]) {
+                Ok(hex) => hex,
+                Err(_) => return None,
+            };
+            match u8::from_str_radix(hex, 16) {
+                Ok(b) => decoded.push(b),
+                Err(_) => return None,
+            }
+            i += 3;
+        } else {
+            decoded.push(bytes[i]);
+            i += 1;
+        }
+    }
+    String::from_utf8(decoded).ok()
+}
+
+fn percent_encode(name: &str) -> String {
+    let mut encoded = String::with_capacity(name.len());
+    for &b in name.as_bytes() {
+        match b {
+            b'A'...b'Z' | b'a'...b'z' | b'0'...b'9' | b'-' | b'.' | b'_' | b'~' | b'/' => {
+                encoded.push(b as char)
+            }
+            _ => encoded.push_str(&format!("%{:02X}", b)),
+        }
+    }
+    encoded
+}
+
+fn escape_html(text: &str) -> String {
+    text.replace("&", "&amp;")
+        .replace("<", "&lt;")
+        .replace(">", "&gt;")
+        .replace("\"", "&quot;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{percent_decode, percent_encode};
+
+    #[test]
+    fn encode_listing_links() {
+        assert_eq!(percent_encode("a b#1?.txt"), "a%20b%231%3F.txt");
+        assert_eq!(percent_encode("100%/"), "100%25/");
+        assert_eq!(percent_decode(&percent_encode("café & <b>")).unwrap(), "café & <b>");
+    }
+}
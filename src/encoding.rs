@@ -0,0 +1,94 @@
+use std::io::Write;
+
+use flate2::Compression;
+use flate2::write::{GzEncoder, ZlibEncoder};
+
+use super::request::Request;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Encoding {
+    Gzip,
+    Deflate,
+}
+
+impl Encoding {
+    pub fn name(&self) -> &'static str {
+        match *self {
+            Encoding::Gzip => "gzip",
+            Encoding::Deflate => "deflate",
+        }
+    }
+
+    pub fn encode(&self, data: &[u8]) -> Result<Vec<u8>, String> {
+        let encoded = match *self {
+            Encoding::Gzip => {
+                let mut encoder = GzEncoder::new(Vec::new(), Compression::Default);
+                encoder.write_all(data).and_then(|_| encoder.finish())
+            }
+            Encoding::Deflate => {
+                let mut encoder = ZlibEncoder::new(Vec::new(), Compression::Default);
+                encoder.write_all(data).and_then(|_| encoder.finish())
+            }
+        };
+        encoded.map_err(|e| format!("{}", e))
+    }
+}
+
+pub fn negotiate(req: &Request) -> Option<Encoding> {
+    let gzip = quality(req, Encoding::Gzip);
+    let deflate = quality(req, Encoding::Deflate);
+
+    if gzip > 0.0 && gzip >= deflate {
+        Some(Encoding::Gzip)
+    } else if deflate > 0.0 {
+        Some(Encoding::Deflate)
+    } else {
+        None
+    }
+}
+
+pub fn accepts(req: &Request, encoding: Encoding) -> bool {
+    quality(req, encoding) > 0.0
+}
+
+fn quality(req: &Request, encoding: Encoding) -> f32 {
+    let header = match req.header_str("Accept-Encoding") {
+        Some(header) => header,
+        None => return 0.0,
+    };
+
+    let mut wildcard = None;
+    for item in header.split(',') {
+        let mut params = item.split(';').map(|p| p.trim());
+        let coding = params.next().unwrap_or("");
+        let q = params
+            .filter_map(|p| if p.starts_with("q=") { p[2..].parse().ok() } else { None })
+            .next()
+            .unwrap_or(1.0);
+
+        if coding.eq_ignore_ascii_case(encoding.name()) {
+            return q;
+        } else if coding == "*" {
+            wildcard = Some(q);
+        }
+    }
+    wildcard.unwrap_or(0.0)
+}
+
+#[cfg(test)]
+mod tests {
+    use request::with_header;
+    use super::{negotiate, Encoding};
+
+    #[test]
+    fn negotiate_encoding() {
+        let negotiate = |accept| negotiate(&with_header("GET", "Accept-Encoding", accept));
+
+        assert_eq!(negotiate("gzip, deflate"), Some(Encoding::Gzip));
+        assert_eq!(negotiate("deflate, gzip;q=0.5"), Some(Encoding::Deflate));
+        assert_eq!(negotiate("GZIP"), Some(Encoding::Gzip));
+        assert_eq!(negotiate("*;q=0.1, gzip;q=0"), Some(Encoding::Deflate));
+        assert_eq!(negotiate("identity"), None);
+        assert_eq!(negotiate("gzip;q=0"), None);
+    }
+}
@@ -0,0 +1,57 @@
+use std::time::Duration;
+
+use super::config::{Event, EventStream};
+use super::transfer::Chunk;
+
+pub const CONTENT_TYPE: &'static str = "text/event-stream";
+
+pub fn format(event: &Event) -> String {
+    let mut text = String::new();
+    if let Some(ref name) = event.event {
+        text.push_str(&format!("event: {}\n", name));
+    }
+    if let Some(ref id) = event.id {
+        text.push_str(&format!("id: {}\n", id));
+    }
+    if let Some(retry) = event.retry {
+        text.push_str(&format!("retry: {}\n", retry));
+    }
+    if let Some(ref data) = event.data {
+        for line in data.lines() {
+            text.push_str(&format!("data: {}\n", line));
+        }
+    }
+    text.push('\n');
+    text
+}
+
+pub fn chunks(stream: &EventStream) -> Vec<Chunk> {
+    stream.events.iter().map(|event| {
+        Chunk {
+            data: format(event).into_bytes(),
+            fake: None,
+            delay: Duration::from_millis(event.delay.unwrap_or(stream.interval)),
+        }
+    }).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use config::Event;
+    use super::format;
+
+    #[test]
+    fn format_event() {
+        let event = Event {
+            event: Some("update".to_owned()),
+            data: Some("{\"a\": 1}\n{\"b\": 2}".to_owned()),
+            id: Some("42".to_owned()),
+            retry: Some(5000),
+            delay: None,
+        };
+        assert_eq!(format(&event),
+                   "event: update\nid: 42\nretry: 5000\ndata: {\"a\": 1}\ndata: {\"b\": 2}\n\n");
+        assert_eq!(format(&Event { event: None, data: None, id: None, retry: None, delay: None }),
+                   "\n");
+    }
+}
@@ -0,0 +1,346 @@
+use std::cmp;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::i64;
+use std::sync::{Arc, Mutex};
+
+use chrono::{TimeZone, UTC};
+use rand::{Rng, SeedableRng, StdRng};
+use rustc_serialize::json::Json;
+
+use super::openapi;
+
+/// Deeper schemas only get their required properties, so recursive schemas end
+const MAX_DEPTH: usize = 8;
+
+const MAX_NESTING: usize = 32;
+
+const MAX_NUMBER: f64 = 1000.0;
+const MAX_ITEMS: u64 = 5;
+const MIN_LENGTH: u64 = 5;
+const MAX_LENGTH: u64 = 12;
+
+/// 2020-01-01, dates are generated over five years
+const MIN_TIMESTAMP: i64 = 1577836800;
+const TIMESTAMP_RANGE: i64 = 5 * 365 * 24 * 3600;
+
+const WORDS: &'static [&'static str] = &[
+    "alpha", "bravo", "charlie", "delta", "echo", "foxtrot", "golf", "hotel", "india", "juliet",
+    "kilo", "lima", "mike", "november", "oscar", "papa", "quebec", "romeo", "sierra", "tango",
+];
+
+#[derive(Clone)]
+pub struct Fake {
+    schema: Arc<Json>,
+    pretty: bool,
+    rng: Arc<Mutex<StdRng>>,
+}
+
+impl fmt::Debug for Fake {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Fake({})", self.schema)
+    }
+}
+
+impl Fake {
+    pub fn new(schema: Json, seed: Option<usize>, pretty: bool) -> Result<Self, String> {
+        let rng = match seed {
+            Some(seed) => StdRng::from_seed(&[seed][..]),
+            None => try!(StdRng::new().map_err(|e| format!("{}", e))),
+        };
+
+        Ok(Fake {
+            schema: Arc::new(schema),
+            pretty: pretty,
+            rng: Arc::new(Mutex::new(rng)),
+        })
+    }
+
+    pub fn body(&self) -> Vec<u8> {
+        let value = {
+            let mut rng = self.rng.lock().unwrap();
+            Generator { root: &self.schema, rng: &mut *rng }.value(&self.schema, 0)
+        };
+
+        if self.pretty {
+            value.pretty().to_string().into_bytes()
+        } else {
+            value.to_string().into_bytes()
+        }
+    }
+}
+
+struct Generator<'a> {
+    root: &'a Json,
+    rng: &'a mut StdRng,
+}
+
+impl<'a> Generator<'a> {
+    fn value(&mut self, schema: &Json, depth: usize) -> Json {
+        let schema = match openapi::resolve(self.root, schema) {
+            Ok(schema) => schema,
+            Err(_) => return Json::Null,
+        };
+        if depth > MAX_NESTING {
+            return Json::Null;
+        }
+
+        if let Some(value) = schema.find("const") {
+            return value.clone();
+        }
+        if let Some(values) = schema.find("enum").and_then(|e| e.as_array()) {
+            if let Some(value) = self.rng.choose(values) {
+                return value.clone();
+            }
+        }
+        if let Some(schemas) = schema.find("allOf").and_then(|s| s.as_array()) {
+            return self.all_of(schema, schemas, depth);
+        }
+        let alternatives = schema.find("oneOf").or_else(|| schema.find("anyOf"))
+            .and_then(|s| s.as_array());
+        if let Some(schemas) = alternatives {
+            if let Some(schema) = self.rng.choose(schemas) {
+                return self.value(schema, depth + 1);
+            }
+        }
+
+        let kind = match schema.find("type") {
+            Some(&Json::String(ref kind)) => &kind[..],
+            Some(&Json::Array(ref kinds)) => {
+                kinds.iter().filter_map(|k| k.as_string()).find(|&k| k != "null").unwrap_or("null")
+            }
+            _ if schema.find("properties").is_some() => "object",
+            _ if schema.find("items").is_some() => "array",
+            _ => "string",
+        };
+
+        match kind {
+            "object" => self.object(schema, depth),
+            "array" => self.array(schema, depth),
+            "integer" => self.integer(schema),
+            "number" => self.number(schema),
+            "boolean" => Json::Boolean(self.rng.gen()),
+            "null" => Json::Null,
+            _ => Json::String(self.string(schema)),
+        }
+    }
+
+    fn all_of(&mut self, schema: &Json, schemas: &[Json], depth: usize) -> Json {
+        let mut object = match self.object(schema, depth) {
+            Json::Object(object) => object,
+            _ => BTreeMap::new(),
+        };
+        for schema in schemas.iter() {
+            match self.value(schema, depth + 1) {
+                Json::Object(properties) => object.extend(properties),
+                value => return value,
+            }
+        }
+        Json::Object(object)
+    }
+
+    fn object(&mut self, schema: &Json, depth: usize) -> Json {
+        let mut object = BTreeMap::new();
+        let properties = match schema.find("properties").and_then(|p| p.as_object()) {
+            Some(properties) => properties,
+            None => return Json::Object(object),
+        };
+        let required: Vec<&str> = schema.find("required").and_then(|r| r.as_array())
+            .map(|r| r.iter().filter_map(|n| n.as_string()).collect())
+            .unwrap_or_default();
+
+        for (name, property) in properties.iter() {
+            // optional properties are left out of some objects
+            let optional = !required.contains(&&name[..]);
+            if optional && (depth >= MAX_DEPTH || self.rng.gen_weighted_bool(4)) {
+                continue;
+            }
+            object.insert(name.clone(), self.value(property, depth + 1));
+        }
+        Json::Object(object)
+    }
+
+    fn array(&mut self, schema: &Json, depth: usize) -> Json {
+        let min = schema.find("minItems").and_then(|m| m.as_u64()).unwrap_or(0);
+        let max = schema.find("maxItems").and_then(|m| m.as_u64())
+            .unwrap_or(min + MAX_ITEMS).max(min);
+        let count = if depth >= MAX_DEPTH { min } else { self.rng.gen_range(min, max + 1) };
+
+        let unique = schema.find("uniqueItems").and_then(|u| u.as_boolean()) == Some(true);
+        let any = Json::Object(BTreeMap::new());
+        let item_schema = schema.find("items").unwrap_or(&any);
+        let mut items: Vec<Json> = Vec::new();
+        // unique items are generated a few times until they differ
+        let mut attempts = 0;
+        while (items.len() as u64) < count && attempts < count * 10 {
+            attempts += 1;
+            let item = self.value(item_schema, depth + 1);
+            if !unique || !items.contains(&item) {
+                items.push(item);
+            }
+        }
+        Json::Array(items)
+    }
+
+    fn integer(&mut self, schema: &Json) -> Json {
+        let (min, max) = bounds(schema, 1.0);
+        let step = schema.find("multipleOf").and_then(|m| m.as_f64())
+            .and_then(|m| if m >= 1.0 { Some(m as i64) } else { None })
+            .unwrap_or(1);
+        // the bounds are clamped so that `high + 1` and the multiples do not overflow
+        let low = cmp::max((min / step as f64).ceil() as i64, i64::MIN / step);
+        let high = cmp::min((max / step as f64).floor() as i64, i64::MAX / step - 1);
+        let value = if high >= low { self.rng.gen_range(low, high + 1) * step } else { min as i64 };
+
+        if value >= 0 {
+            Json::U64(value as u64)
+        } else {
+            Json::I64(value)
+        }
+    }
+
+    fn number(&mut self, schema: &Json) -> Json {
+        let (min, max) = bounds(schema, 0.01);
+        let value = if max > min { self.rng.gen_range(min, max) } else { min };
+        // two decimals are enough for fake data, unless they leave the bounds
+        let rounded = (value * 100.0).round() / 100.0;
+        Json::F64(if rounded >= min && rounded <= max { rounded } else { value })
+    }
+
+    fn string(&mut self, schema: &Json) -> String {
+        let min = schema.find("minLength").and_then(|m| m.as_u64());
+        let max = schema.find("maxLength").and_then(|m| m.as_u64());
+
+        let format = schema.find("format").and_then(|f| f.as_string()).unwrap_or("");
+        let text = match format {
+            "date-time" => self.date().format("%Y-%m-%dT%H:%M:%SZ").to_string(),
+            "date" => self.date().format("%Y-%m-%d").to_string(),
+            "time" => self.date().format("%H:%M:%S").to_string(),
+            "email" => format!("{}@example.com", self.word()),
+            "uri" | "url" => format!("https://example.com/{}", self.word()),
+            "hostname" => format!("{}.example.com", self.word()),
+            "ipv4" => format!("10.{}.{}.{}", self.rng.gen::<u8>(), self.rng.gen::<u8>(),
+                              self.rng.gen_range(1, 255)),
+            "uuid" => self.uuid(),
+            _ => {
+                let min = min.unwrap_or(MIN_LENGTH);
+                let max = max.unwrap_or(min.max(MAX_LENGTH)).max(min);
+                let length = self.rng.gen_range(min, max + 1) as usize;
+                let mut text = String::new();
+                while text.len() < length {
+                    if !text.is_empty() {
+                        text.push(' ');
+                    }
+                    text.push_str(self.word());
+                }
+                text.truncate(length);
+                if text.ends_with(' ') {
+                    text.pop();
+                    text.push('x');
+                }
+                return text;
+            }
+        };
+
+        // formatted strings only get padded or cut to fit the bounds
+        let mut text = text;
+        if let Some(min) = min {
+            while (text.chars().count() as u64) < min {
+                text.push('x');
+            }
+        }
+        if let Some(max) = max {
+            text = text.chars().take(max as usize).collect();
+        }
+        text
+    }
+
+    fn word(&mut self) -> &'static str {
+        self.rng.choose(WORDS).map(|w| *w).unwrap_or("fake")
+    }
+
+    fn date(&mut self) -> ::chrono::DateTime<UTC> {
+        UTC.timestamp(MIN_TIMESTAMP + self.rng.gen_range(0, TIMESTAMP_RANGE), 0)
+    }
+
+    fn uuid(&mut self) -> String {
+        let mut bytes = [0u8; 16];
+        self.rng.fill_bytes(&mut bytes);
+        bytes[6] = (bytes[6] & 0x0f) | 0x40;
+        bytes[8] = (bytes[8] & 0x3f) | 0x80;
+        let hex: String = bytes.iter().map(|b| format!("{:02x}", b)).collect();
+        format!("{}-{}-{}-{}-{}", &hex[..8], &hex[8..12], &hex[12..16], &hex[16..20], &hex[20..])
+    }
+}
+
+/// The exclusive bounds of OpenAPI 3.0 (booleans) and JSON Schema (numbers) are moved by `offset`
+fn bounds(schema: &Json, offset: f64) -> (f64, f64) {
+    let number = |name: &str| schema.find(name).and_then(|n| n.as_f64());
+    let exclusive = |name: &str| schema.find(name).and_then(|e| e.as_boolean()) == Some(true);
+
+    let min = match (number("minimum"), number("exclusiveMinimum")) {
+        (Some(min), _) if exclusive("exclusiveMinimum") => Some(min + offset),
+        (Some(min), _) => Some(min),
+        (None, Some(min)) => Some(min + offset),
+        (None, None) => None,
+    };
+    let max = match (number("maximum"), number("exclusiveMaximum")) {
+        (Some(max), _) if exclusive("exclusiveMaximum") => Some(max - offset),
+        (Some(max), _) => Some(max),
+        (None, Some(max)) => Some(max - offset),
+        (None, None) => None,
+    };
+
+    match (min, max) {
+        (Some(min), Some(max)) => (min, max),
+        (Some(min), None) => (min, min + MAX_NUMBER),
+        (None, Some(max)) => (max.min(0.0), max),
+        (None, None) => (0.0, MAX_NUMBER),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rustc_serialize::json::Json;
+    use super::Fake;
+    use super::super::schema;
+
+    #[test]
+    fn generate_valid_values() {
+        let spec = Json::from_str(r##"{
+            "type": "array", "minItems": 2, "uniqueItems": true,
+            "items": {"$ref": "#/definitions/User"},
+            "definitions": {"User": {
+                "type": "object",
+                "required": ["id", "email", "role", "score"],
+                "properties": {
+                    "id": {"type": "integer", "minimum": 1, "exclusiveMaximum": 10, "multipleOf": 3},
+                    "email": {"type": "string", "format": "email", "maxLength": 20},
+                    "name": {"type": "string", "minLength": 3, "maxLength": 4},
+                    "role": {"enum": ["admin", "guest"]},
+                    "score": {"type": "number", "minimum": 0.5, "maximum": 0.6},
+                    "friends": {"type": "array", "items": {"$ref": "#/definitions/User"}}}}}}"##)
+            .unwrap();
+
+        let fake = Fake::new(spec.clone(), Some(7), false).unwrap();
+        for _ in 0..20 {
+            let value = Json::from_str(&String::from_utf8(fake.body()).unwrap()).unwrap();
+            let mut errors = Vec::new();
+            schema::validate(&spec, &spec, &value, "body", &mut errors);
+            assert!(errors.is_empty(), "{:?} in {}", errors, value);
+        }
+
+        let body = |seed| Fake::new(spec.clone(), Some(seed), false).unwrap().body();
+        assert_eq!(body(42), body(42));
+    }
+
+    #[test]
+    fn generate_large_integers() {
+        for schema in &[r#"{"type": "integer", "minimum": 0, "maximum": 1e19}"#,
+                        r#"{"type": "integer", "minimum": -1e19, "maximum": 1e19, "multipleOf": 7}"#] {
+            let fake = Fake::new(Json::from_str(schema).unwrap(), Some(1), false).unwrap();
+            let value = Json::from_str(&String::from_utf8(fake.body()).unwrap()).unwrap();
+            assert!(value.is_i64() || value.is_u64());
+        }
+    }
+}
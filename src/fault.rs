@@ -0,0 +1,91 @@
+use std::sync::{Arc, Mutex};
+
+use rand::{Rng, SeedableRng, StdRng};
+
+use super::config::{self, FaultKind};
+
+#[derive(Clone, Debug)]
+pub struct Fault {
+    kind: FaultKind,
+    probability: f64,
+    rng: Arc<Mutex<StdRng>>,
+}
+
+impl Fault {
+    pub fn new(kind: FaultKind, probability: f64, seed: Option<usize>) -> Result<Self, String> {
+        if probability < 0.0 || probability > 1.0 {
+            return Err(format!("Invalid fault probability: {}", probability));
+        }
+
+        let rng = match seed {
+            Some(seed) => StdRng::from_seed(&[seed][..]),
+            None => try!(StdRng::new().map_err(|e| format!("{}", e))),
+        };
+
+        Ok(Fault {
+            kind: kind,
+            probability: probability,
+            rng: Arc::new(Mutex::new(rng)),
+        })
+    }
+
+    pub fn from_config(config: &config::Fault) -> Result<Self, String> {
+        Fault::new(config.kind.clone(), config.probability, config.seed)
+    }
+
+    pub fn trigger(&self) -> Option<FaultKind> {
+        if self.probability >= 1.0 {
+            return Some(self.kind.clone());
+        }
+
+        let mut rng = self.rng.lock().unwrap();
+        if rng.next_f64() < self.probability {
+            Some(self.kind.clone())
+        } else {
+            None
+        }
+    }
+
+    pub fn garbage(&self, len: usize) -> Vec<u8> {
+        let mut data = vec![0u8; len];
+        self.rng.lock().unwrap().fill_bytes(&mut data);
+        data
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use config::FaultKind;
+    use super::Fault;
+
+    #[test]
+    fn invalid_probability() {
+        assert!(Fault::new(FaultKind::Empty, -0.1, None).is_err());
+        assert!(Fault::new(FaultKind::Empty, 1.5, None).is_err());
+    }
+
+    #[test]
+    fn trigger_always_and_never() {
+        let always = Fault::new(FaultKind::Truncated, 1.0, None).unwrap();
+        let never = Fault::new(FaultKind::Truncated, 0.0, None).unwrap();
+        for _ in 0..100 {
+            match always.trigger() {
+                Some(FaultKind::Truncated) => {}
+                other => panic!("unexpected fault {:?}", other),
+            }
+            assert!(never.trigger().is_none());
+        }
+    }
+
+    #[test]
+    fn seeded_faults_repeat() {
+        let first = Fault::new(FaultKind::Reset, 0.5, Some(42)).unwrap();
+        let second = Fault::new(FaultKind::Reset, 0.5, Some(42)).unwrap();
+        let triggers = (0..100).map(|_| first.trigger().is_some()).collect::<Vec<_>>();
+        assert_eq!(triggers, (0..100).map(|_| second.trigger().is_some()).collect::<Vec<_>>());
+        assert!(triggers.iter().any(|&t| t));
+        assert!(triggers.iter().any(|&t| !t));
+        assert_eq!(first.garbage(32), second.garbage(32));
+        assert_eq!(first.garbage(16).len(), 16);
+    }
+}
@@ -1,18 +1,52 @@
+use std::cmp;
 use std::fs::File;
-use std::io::Read;
+use std::io::{Cursor, Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+use std::str;
+use std::sync::Arc;
+use std::time::Duration;
 
+use rand;
 use rotor_http::server::Response;
 
-use super::config::Content;
+use super::conditional::{Precondition, Validators};
+use super::config::FaultKind;
+use super::encoding::{self, Encoding};
+use super::fake::Fake;
+use super::fault::Fault;
 use super::http_status;
+use super::mime;
+use super::negotiation;
+use super::openapi::Validation;
+use super::range::{self, Multipart, RangeError};
+use super::request::{Headers, Request};
+use super::transfer::{self, Chunk, Transfer};
 
-type Headers = Vec<(String, Vec<u8>)>;
+const MAX_COMPRESSED_FILE: u64 = 1024 * 1024;
+
+#[derive(Clone, Debug)]
+pub enum Body {
+    Data(Vec<u8>),
+    File(PathBuf),
+    Chunks {
+        chunks: Vec<Chunk>,
+        repeat: bool,
+    },
+    Fake(Fake),
+}
 
 #[derive(Clone, Debug)]
 pub struct Handler {
     pub status: u16,
-    content: Option<Content>,
+    content: Option<Body>,
     headers: Headers,
+    fault: Option<Fault>,
+    throttle: Option<u64>,
+    compression: Option<u64>,
+    compress_files: bool,
+    variants: Vec<(String, Handler)>,
+    chunked: bool,
+    validation: Option<Arc<Validation>>,
 }
 
 impl Handler {
@@ -21,10 +55,17 @@ impl Handler {
             status: status,
             content: None,
             headers: Headers::new(),
+            fault: None,
+            throttle: None,
+            compression: None,
+            compress_files: false,
+            variants: Vec::new(),
+            chunked: false,
+            validation: None,
         }
     }
 
-    pub fn set_content(&mut self, content: Option<Content>) {
+    pub fn set_content(&mut self, content: Option<Body>) {
         self.content = content;
     }
 
@@ -37,49 +78,429 @@ impl Handler {
         self
     }
 
-    pub fn handle(&self, res: &mut Response) -> Result<(), String> {
-        let (status_code, status_text) = (self.status, http_status::description(self.status));
-        res.status(status_code, status_text);
+    pub fn set_fault(&mut self, fault: Option<Fault>) {
+        self.fault = fault;
+    }
+
+    pub fn fault(&self) -> Option<FaultKind> {
+        self.fault.as_ref().and_then(|f| f.trigger())
+    }
+
+    pub fn set_throttle(&mut self, throttle: Option<u64>) {
+        self.throttle = throttle.and_then(|t| if t > 0 { Some(t) } else { None });
+    }
+
+    pub fn set_compression(&mut self, min_size: Option<u64>) {
+        self.compression = min_size;
+    }
+
+    pub fn set_compress_files(&mut self, compress_files: bool) {
+        self.compress_files = compress_files;
+    }
+
+    pub fn add_variant(&mut self, media_type: String, variant: Handler) {
+        self.variants.push((media_type, variant));
+    }
+
+    pub fn set_chunked(&mut self, chunked: bool) {
+        self.chunked = chunked;
+    }
+
+    pub fn set_validation(&mut self, validation: Option<Validation>) {
+        self.validation = validation.map(Arc::new);
+    }
+
+    pub fn validation(&self) -> Option<&Validation> {
+        self.validation.as_ref().map(|v| &**v)
+    }
+
+    pub fn transfer_delay(&self) -> Option<Duration> {
+        if !self.variants.is_empty() {
+            return self.variants.iter().filter_map(|&(_, ref v)| v.transfer_delay()).min();
+        }
+
+        let streamed = match self.content {
+            Some(Body::Data(ref data)) => data.len() as u64 > transfer::BLOCK_SIZE,
+            Some(Body::File(_)) | Some(Body::Fake(_)) => true,
+            Some(Body::Chunks { ref chunks, .. }) => return chunks.first().map(|c| c.delay),
+            None => false,
+        };
+
+        if streamed || self.throttle.is_some() {
+            Some(transfer::interval(self.throttle))
+        } else {
+            None
+        }
+    }
+
+    pub fn handle(&self, req: &Request, res: &mut Response) -> Result<Option<Transfer>, String> {
+        if !self.variants.is_empty() {
+            let media_types = self.variants.iter().map(|&(ref media_type, _)| &**media_type);
+            return match negotiation::select(req, media_types) {
+                Some(i) => self.variants[i].1.handle(req, res),
+                None => {
+                    self.send_not_acceptable(res);
+                    Ok(None)
+                }
+            };
+        }
+
         match self.content {
-            Some(Content::Data(ref data)) => {
-                res.add_length(data.len() as u64).unwrap();
+            Some(Body::Data(ref data)) => self.handle_data(data, req, res),
+            Some(Body::Fake(ref fake)) => self.handle_data(&fake.body(), req, res),
+            Some(Body::File(ref path)) => self.handle_file(path, req, res),
+            Some(Body::Chunks { ref chunks, repeat }) => {
+                res.status(self.status, http_status::description(self.status));
+                res.add_chunked().unwrap();
+                let has_body = write_headers(&self.headers, res);
+                if !has_body || chunks.is_empty() {
+                    res.done();
+                    return Ok(None);
+                }
+                Ok(Some(Transfer::chunks(chunks.clone(), repeat)))
+            }
+            None => {
+                res.status(self.status, http_status::description(self.status));
+                res.add_length(0).unwrap();
                 write_headers(&self.headers, res);
-                res.write_body(data.as_bytes());
+                res.done();
+                Ok(None)
             }
-            Some(Content::File(ref path)) => {
-                try!(File::open(path)
-                    .and_then(|file| {
-                        let metadata = try!(file.metadata());
-                        res.add_length(metadata.len()).unwrap();
-                        write_headers(&self.headers, res);
-                        Ok(file)
-                    })
-                    .and_then(|mut file| {
-                        let mut buf = [0u8; 1024];
-                        let mut bytes_read = try!(file.read(&mut buf));
-                        while bytes_read > 0 {
-                            res.write_body(&buf[..bytes_read]);
-                            bytes_read = try!(file.read(&mut buf));
-                        }
-                        Ok(())
-                    })
-                    .map_err(|e| format!("{}", e))
-                );
+        }
+    }
+
+    fn handle_data(&self, data: &[u8], req: &Request, res: &mut Response)
+        -> Result<Option<Transfer>, String>
+    {
+        let encoding = self.negotiate_encoding(data.len() as u64, req);
+        let body = match encoding {
+            Some(encoding) => try!(encoding.encode(data)),
+            None => data.to_owned(),
+        };
+
+        let validators = Validators::for_data(&body);
+        if self.check_preconditions(&validators, req, res) {
+            return Ok(None);
+        }
+
+        let length = body.len() as u64;
+        res.status(self.status, http_status::description(self.status));
+        self.add_length(length, res);
+        validators.add_headers(res);
+        self.add_encoding_headers(encoding, res);
+        let has_body = write_headers(&self.headers, res);
+        self.send_body(Cursor::new(body), length, has_body, res)
+    }
+
+    fn handle_file(&self, path: &Path, req: &Request, res: &mut Response)
+        -> Result<Option<Transfer>, String>
+    {
+        if self.compression.is_some() && encoding::accepts(req, Encoding::Gzip) {
+            let precompressed = precompressed_path(path);
+            if precompressed.is_file() {
+                return self.send_file(&precompressed, Some(Encoding::Gzip), req, res);
             }
-            None => {
+        }
+
+        self.send_file(path, None, req, res)
+    }
+
+    fn send_file(&self, path: &Path, encoding: Option<Encoding>, req: &Request, res: &mut Response)
+        -> Result<Option<Transfer>, String>
+    {
+        let (mut file, metadata) = try!(File::open(path)
+            .and_then(|file| {
+                let metadata = try!(file.metadata());
+                Ok((file, metadata))
+            })
+            .map_err(|e| format!("{}", e))
+        );
+        let length = metadata.len();
+
+        // files are only compressed on the fly when the whole content is
+        // sent, and it is small enough to be compressed in memory
+        let compressible = self.compress_files && length <= MAX_COMPRESSED_FILE &&
+            !self.header("Content-Type")
+                .and_then(|t| str::from_utf8(t).ok())
+                .map_or(false, mime::is_compressed);
+        let compress = match encoding {
+            None if compressible && req.header("Range").is_none() => {
+                self.negotiate_encoding(length, req)
+            }
+            _ => None,
+        };
+
+        let mut validators = Validators::for_file(&metadata);
+        if let Some(compress) = compress {
+            validators = validators.encoded(compress);
+        }
+        if self.check_preconditions(&validators, req, res) {
+            return Ok(None);
+        }
+
+        if let Some(compress) = compress {
+            let mut data = Vec::new();
+            try!(file.read_to_end(&mut data).map_err(|e| format!("{}", e)));
+            let body = try!(compress.encode(&data));
+            let length = body.len() as u64;
+            res.status(self.status, http_status::description(self.status));
+            self.add_length(length, res);
+            validators.add_headers(res);
+            self.add_encoding_headers(Some(compress), res);
+            let has_body = write_headers(&self.headers, res);
+            return self.send_body(Cursor::new(body), length, has_body, res);
+        }
+
+        let ranges = match req.header_str("Range") {
+            Some(header) if self.status == 200 && req.method == "GET"
+                && validators.range_applies(req) => {
+                Some(range::parse(header, length))
+            }
+            _ => None,
+        };
+
+        match ranges {
+            Some(Ok(ref ranges)) if ranges.len() == 1 => {
+                let range = ranges[0];
+                try!(file.seek(SeekFrom::Start(range.start)).map_err(|e| format!("{}", e)));
+                let status = http_status::PartialContent;
+                res.status(status.code(), status.description());
+                self.add_length(range.len(), res);
+                res.add_header("Content-Range", range.content_range(length).as_bytes()).unwrap();
+                res.add_header("Accept-Ranges", b"bytes").unwrap();
+                validators.add_headers(res);
+                self.add_encoding_headers(encoding, res);
+                let has_body = write_headers(&self.headers, res);
+                self.send_body(file.take(range.len()), range.len(), has_body, res)
+            }
+            Some(Ok(ref ranges)) => {
+                let boundary = format!("{:016x}", rand::random::<u64>());
+                let body = Multipart::new(file, ranges, length, self.header("Content-Type"),
+                                          &boundary);
+                let body_length = body.len();
+
+                let status = http_status::PartialContent;
+                res.status(status.code(), status.description());
+                self.add_length(body_length, res);
+                res.add_header("Content-Type",
+                               format!("multipart/byteranges; boundary={}", boundary).as_bytes())
+                    .unwrap();
+                res.add_header("Accept-Ranges", b"bytes").unwrap();
+                validators.add_headers(res);
+                self.add_encoding_headers(encoding, res);
+                for &(ref k, ref v) in self.headers.iter() {
+                    if !k.eq_ignore_ascii_case("Content-Type") {
+                        res.add_header(k, v).unwrap();
+                    }
+                }
+                let has_body = res.done_headers().unwrap();
+                self.send_body(body, body_length, has_body, res)
+            }
+            Some(Err(RangeError::Unsatisfiable)) => {
+                let status = http_status::RequestedRangeNotSatisfiable;
+                res.status(status.code(), status.description());
                 res.add_length(0).unwrap();
+                res.add_header("Content-Range", format!("bytes */{}", length).as_bytes()).unwrap();
                 write_headers(&self.headers, res);
+                res.done();
+                Ok(None)
             }
+            Some(Err(RangeError::Invalid)) | Some(Err(RangeError::TooMany)) | None => {
+                let (status_code, status_text) = (self.status, http_status::description(self.status));
+                res.status(status_code, status_text);
+                self.add_length(length, res);
+                if status_code == 200 {
+                    res.add_header("Accept-Ranges", b"bytes").unwrap();
+                }
+                validators.add_headers(res);
+                self.add_encoding_headers(encoding, res);
+                let has_body = write_headers(&self.headers, res);
+                self.send_body(file, length, has_body, res)
+            }
+        }
+    }
+
+    fn add_length(&self, length: u64, res: &mut Response) {
+        if self.chunked {
+            res.add_chunked().unwrap();
+        } else {
+            res.add_length(length).unwrap();
+        }
+    }
+
+    fn send_not_acceptable(&self, res: &mut Response) {
+        let body = self.variants.iter()
+            .map(|&(ref media_type, _)| format!("{}\n", media_type))
+            .collect::<String>();
+
+        let status = http_status::NotAcceptable;
+        res.status(status.code(), status.description());
+        res.add_length(body.len() as u64).unwrap();
+        res.add_header("Content-Type", b"text/plain").unwrap();
+        res.add_header("Vary", b"Accept").unwrap();
+        for &(ref k, ref v) in self.headers.iter() {
+            if !k.eq_ignore_ascii_case("Content-Type") && !k.eq_ignore_ascii_case("Vary") {
+                res.add_header(k, v).unwrap();
+            }
+        }
+        if res.done_headers().unwrap() {
+            res.write_body(body.as_bytes());
+        }
+        res.done();
+    }
+
+    fn negotiate_encoding(&self, length: u64, req: &Request) -> Option<Encoding> {
+        match self.compression {
+            Some(min_size) if length >= min_size => encoding::negotiate(req),
+            _ => None,
+        }
+    }
+
+    fn add_encoding_headers(&self, encoding: Option<Encoding>, res: &mut Response) {
+        if let Some(encoding) = encoding {
+            res.add_header("Content-Encoding", encoding.name().as_bytes()).unwrap();
+        }
+        if self.compression.is_some() {
+            res.add_header("Vary", b"Accept-Encoding").unwrap();
+        }
+    }
+
+    fn check_preconditions(&self, validators: &Validators, req: &Request, res: &mut Response)
+        -> bool
+    {
+        if self.status < 200 || self.status >= 300 {
+            return false;
         }
+
+        match validators.evaluate(req) {
+            Precondition::Passed => return false,
+            Precondition::NotModified => {
+                let status = http_status::NotModified;
+                res.status(status.code(), status.description());
+                validators.add_headers(res);
+            }
+            Precondition::Failed => {
+                let status = http_status::PreconditionFailed;
+                res.status(status.code(), status.description());
+                res.add_length(0).unwrap();
+            }
+        }
+        write_headers(&self.headers, res);
         res.done();
 
-        Ok(())
+        true
+    }
+
+    fn send_body<R>(&self, mut source: R, length: u64, has_body: bool, res: &mut Response)
+        -> Result<Option<Transfer>, String>
+        where R: Read + Send + 'static
+    {
+        if !has_body || length == 0 {
+            res.done();
+            return Ok(None);
+        }
+
+        if self.throttle.is_some() || length > transfer::BLOCK_SIZE {
+            return Ok(Some(Transfer::new(source, length, self.throttle)));
+        }
+
+        let mut buf = [0u8; 1024];
+        let mut bytes_read = try!(source.read(&mut buf).map_err(|e| format!("{}", e)));
+        while bytes_read > 0 {
+            res.write_body(&buf[..bytes_read]);
+            bytes_read = try!(source.read(&mut buf).map_err(|e| format!("{}", e)));
+        }
+        res.done();
+
+        Ok(None)
+    }
+
+    pub fn header(&self, name: &str) -> Option<&[u8]> {
+        self.headers.iter()
+            .find(|&&(ref k, _)| k.eq_ignore_ascii_case(name))
+            .map(|&(_, ref v)| &v[..])
+    }
+
+    /// Leaves the response unfinished, bytes rotor-http cannot encode go to the raw output
+    pub fn inject_fault(&self, kind: &FaultKind, res: &mut Response)
+        -> Result<Option<Vec<u8>>, String>
+    {
+        let (status_code, status_text) = (self.status, http_status::description(self.status));
+        match *kind {
+            FaultKind::Reset | FaultKind::Empty => Ok(None),
+            FaultKind::Truncated => {
+                let body = try!(self.read_content());
+                res.status(status_code, status_text);
+                // an empty body still announces one byte so it is never complete
+                res.add_length(cmp::max(body.len(), 1) as u64).unwrap();
+                write_headers(&self.headers, res);
+                res.write_body(&body[..body.len() / 2]);
+                Ok(None)
+            }
+            FaultKind::MalformedChunk => {
+                let body = try!(self.read_content());
+                let chunk = &body[..body.len() / 2];
+                let mut raw = status_line(status_code, status_text);
+                for &(ref k, ref v) in self.headers.iter() {
+                    raw.extend(k.as_bytes());
+                    raw.extend(b": ");
+                    raw.extend(v);
+                    raw.extend(b"\r\n");
+                }
+                raw.extend(b"Transfer-Encoding: chunked\r\n\r\n".iter());
+                if !chunk.is_empty() {
+                    raw.extend(format!("{:x}\r\n", chunk.len()).as_bytes());
+                    raw.extend(chunk);
+                    raw.extend(b"\r\n");
+                }
+                raw.extend(b"zz");
+                Ok(Some(raw))
+            }
+            FaultKind::Garbage(len) => {
+                let mut raw = status_line(status_code, status_text);
+                raw.extend(self.fault.as_ref().map(|f| f.garbage(len)).unwrap_or(vec![0; len]));
+                Ok(Some(raw))
+            }
+        }
+    }
+
+    fn read_content(&self) -> Result<Vec<u8>, String> {
+        match self.content {
+            Some(Body::Data(ref data)) => Ok(data.clone()),
+            Some(Body::Fake(ref fake)) => Ok(fake.body()),
+            Some(Body::Chunks { ref chunks, .. }) => {
+                Ok(chunks.iter().flat_map(|c| c.body().into_owned()).collect())
+            }
+            Some(Body::File(ref path)) => {
+                let mut data = Vec::new();
+                try!(File::open(path)
+                    .and_then(|mut file| file.read_to_end(&mut data))
+                    .map_err(|e| format!("{}", e)));
+                Ok(data)
+            }
+            None => Ok(Vec::new()),
+        }
     }
 }
 
-fn write_headers(headers: &Headers, res: &mut Response) {
+fn status_line(code: u16, text: &str) -> Vec<u8> {
+    format!("HTTP/1.1 {} {}\r\n", code, text).into_bytes()
+}
+
+fn precompressed_path(path: &Path) -> PathBuf {
+    let mut name = path.file_name().map(|n| n.to_owned()).unwrap_or_default();
+    name.push(".gz");
+    path.with_file_name(name)
+}
+
+fn add_headers(headers: &Headers, res: &mut Response) {
     for &(ref k, ref v) in headers.iter() {
         res.add_header(k, v).unwrap();
     }
-    res.done_headers().unwrap();
 }
+
+fn write_headers(headers: &Headers, res: &mut Response) -> bool {
+    add_headers(headers, res);
+    res.done_headers().unwrap()
+}
+
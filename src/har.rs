@@ -0,0 +1,163 @@
+use std::fs::File;
+use std::path::Path;
+
+use rustc_serialize::base64::FromBase64;
+use rustc_serialize::json::Json;
+
+use super::record::Recorder;
+use super::request::Headers;
+
+const SKIPPED_HEADERS: &'static [&'static str] = &["Content-Encoding"];
+
+pub fn import(har_file: &Path, dir: &Path) -> Result<usize, String> {
+    let har = try!(File::open(har_file)
+        .map_err(|e| format!("Error opening {}: {}", har_file.display(), e))
+        .and_then(|mut file| Json::from_reader(&mut file)
+            .map_err(|e| format!("Invalid HAR file {}: {}", har_file.display(), e))));
+
+    let entries = try!(har.find_path(&["log", "entries"])
+        .and_then(|e| e.as_array())
+        .ok_or(format!("Invalid HAR file {}: missing log entries", har_file.display())));
+
+    let mut recorder = Recorder::new(dir.to_path_buf());
+    let mut count = 0;
+    for (i, entry) in entries.iter().enumerate() {
+        let method = entry.find_path(&["request", "method"]).and_then(|m| m.as_string());
+        let url = entry.find_path(&["request", "url"]).and_then(|u| u.as_string());
+        let status = entry.find_path(&["response", "status"]).and_then(|s| s.as_u64());
+
+        let (method, url, status) = match (method, url, status) {
+            // status 0 is used for requests that did not get a response
+            (Some(method), Some(url), Some(status)) if status > 0 => (method, url, status),
+            _ => {
+                warn!("Skipping entry {} of {}", i, har_file.display());
+                continue;
+            }
+        };
+        if method == "HEAD" {
+            continue;
+        }
+
+        let body = try!(content(entry).map_err(|e| format!("Entry {} ({}): {}", i, url, e)));
+        if try!(recorder.record(method, &url_path(url), status as u16, &headers(entry), &body)) {
+            count += 1;
+        }
+    }
+
+    try!(recorder.write());
+    Ok(count)
+}
+
+fn headers(entry: &Json) -> Headers {
+    let headers = match entry.find_path(&["response", "headers"]).and_then(|h| h.as_array()) {
+        Some(headers) => headers,
+        None => return Vec::new(),
+    };
+
+    headers.iter()
+        .filter_map(|header| {
+            let name = header.find("name").and_then(|n| n.as_string());
+            let value = header.find("value").and_then(|v| v.as_string());
+            match (name, value) {
+                // HTTP/2 pseudo headers start with `:`
+                (Some(name), Some(value)) if !name.starts_with(':') &&
+                    !SKIPPED_HEADERS.iter().any(|h| h.eq_ignore_ascii_case(name)) => {
+                    Some((name.to_owned(), value.as_bytes().to_owned()))
+                }
+                _ => None,
+            }
+        })
+        .collect()
+}
+
+fn content(entry: &Json) -> Result<Vec<u8>, String> {
+    let text = match entry.find_path(&["response", "content", "text"]).and_then(|t| t.as_string()) {
+        Some(text) => text,
+        None => return Ok(Vec::new()),
+    };
+
+    match entry.find_path(&["response", "content", "encoding"]).and_then(|e| e.as_string()) {
+        Some("base64") => text.from_base64().map_err(|e| format!("{}", e)),
+        Some(encoding) => Err(format!("Unknown content encoding {}", encoding)),
+        None => Ok(text.as_bytes().to_owned()),
+    }
+}
+
+fn url_path(url: &str) -> String {
+    let url = url.split('#').next().unwrap_or("");
+    let rest = match url.find("://") {
+        Some(i) => &url[i + 3..],
+        None => url,
+    };
+    match rest.find(|c| c == '/' || c == '?') {
+        Some(i) if rest[i..].starts_with('?') => format!("/{}", &rest[i..]),
+        Some(i) => rest[i..].to_owned(),
+        None => "/".to_owned(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::fs::{self, File};
+    use std::io::Write;
+
+    use config::{self, Route};
+    use record::CONFIG_FILE;
+    use super::{import, url_path};
+
+    const HAR: &'static str = r#"{"log": {"entries": [
+        {"request": {"method": "GET", "url": "http://example.com/"},
+         "response": {"status": 200, "headers": [{"name": "Content-Type", "value": "text/html"}],
+                      "content": {"text": "<p>Home</p>"}}},
+        {"request": {"method": "GET", "url": "http://example.com/api/users?page=2"},
+         "response": {"status": 200,
+                      "headers": [{"name": "Content-Type", "value": "application/json"},
+                                  {"name": "Content-Encoding", "value": "gzip"},
+                                  {"name": ":status", "value": "200"}],
+                      "content": {"text": "[{\"name\": \"john\"}]"}}},
+        {"request": {"method": "POST", "url": "http://example.com/api/users"},
+         "response": {"status": 201, "headers": [],
+                      "content": {"text": "AAEC", "encoding": "base64"}}},
+        {"request": {"method": "GET", "url": "http://example.com/blocked"},
+         "response": {"status": 0, "headers": []}}
+    ]}}"#;
+
+    #[test]
+    fn import_har_file() {
+        let dir = env::temp_dir().join("responder-har-test");
+        let har_file = dir.join("requests.har");
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        File::create(&har_file).unwrap().write_all(HAR.as_bytes()).unwrap();
+
+        assert_eq!(import(&har_file, &dir).unwrap(), 3);
+
+        let config = config::read_config(&dir.join(CONFIG_FILE)).unwrap();
+        assert_eq!(config.routes.keys().collect::<Vec<_>>(), vec!["/$", "/api"]);
+        match config.routes["/$"] {
+            Route::Handler(ref route) => assert_eq!(route.handlers().len(), 1),
+            ref route => panic!("Unexpected route {:?}", route),
+        }
+        let include = match config.routes["/api"] {
+            Route::Include(ref path) => path.clone(),
+            ref route => panic!("Unexpected route {:?}", route),
+        };
+
+        let routes = config::read_config_include(&include).unwrap();
+        assert_eq!(routes.keys().collect::<Vec<_>>(), vec!["/users$", "/users\\?page=2$"]);
+        match routes["/users$"] {
+            Route::Handler(ref route) => assert_eq!(route.handlers().len(), 1),
+            ref route => panic!("Unexpected route {:?}", route),
+        }
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn path_of_url() {
+        assert_eq!(url_path("https://example.com/api/users?page=2#top"), "/api/users?page=2");
+        assert_eq!(url_path("http://example.com:8080"), "/");
+        assert_eq!(url_path("http://example.com?q=1"), "/?q=1");
+    }
+}
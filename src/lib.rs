@@ -1,10 +1,19 @@
 #[macro_use(rotor_compose)]
 extern crate rotor;
 extern crate rotor_http;
+extern crate rotor_stream;
 extern crate rotor_tools;
 extern crate quire;
 extern crate rustc_serialize;
 extern crate regex;
+extern crate rand;
+extern crate chrono;
+extern crate flate2;
+extern crate httparse;
+extern crate sha1;
+extern crate libc;
+#[cfg(feature = "tls")]
+extern crate openssl;
 
 #[macro_use]
 extern crate log;
@@ -15,6 +24,26 @@ pub mod config;
 pub mod builder;
 pub mod context;
 pub mod http_status;
+pub mod fault;
+pub mod transfer;
+pub mod request;
+pub mod range;
+pub mod conditional;
+pub mod mime;
+pub mod directory;
+pub mod encoding;
+pub mod negotiation;
+pub mod literal;
+pub mod event_stream;
+pub mod websocket;
+pub mod proxy;
+pub mod record;
+pub mod har;
+pub mod openapi;
+pub mod schema;
+pub mod fake;
+#[cfg(feature = "tls")]
+pub mod tls;
 
 pub use server::Responder;
 pub use handler::Handler;
@@ -0,0 +1,156 @@
+use std::rc::Rc;
+
+use quire::ast::{self, Ast, NullKind, ScalarKind, Tag};
+use quire::parser;
+use quire::sky::Error;
+use quire::validate::{Pos, Validator};
+use rustc_serialize::base64::FromBase64;
+use rustc_serialize::hex::FromHex;
+use rustc_serialize::json::Json;
+
+#[derive(Default)]
+pub struct JsonValue {
+    optional: bool,
+}
+
+impl JsonValue {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    pub fn optional(mut self) -> Self {
+        self.optional = true;
+        self
+    }
+}
+
+impl Validator for JsonValue {
+    fn validate(&self, ast: Ast) -> (Ast, Vec<Error>) {
+        let pos = ast.pos();
+        let json = to_json(&ast).to_string();
+        (Ast::Scalar(pos, Tag::NonSpecific, ScalarKind::Quoted, json), Vec::new())
+    }
+
+    fn default(&self, pos: Pos) -> Option<Ast> {
+        if self.optional {
+            Some(Ast::Null(pos, Tag::NonSpecific, NullKind::Implicit))
+        } else {
+            None
+        }
+    }
+}
+
+pub struct YamlText;
+
+impl Validator for YamlText {
+    fn validate(&self, ast: Ast) -> (Ast, Vec<Error>) {
+        let pos = ast.pos();
+        let text = match ast {
+            Ast::Scalar(_, _, _, text) => text,
+            ast => {
+                let err = Error::validation_error(&pos, "Yaml content must be a string".to_owned());
+                return (ast, vec![err]);
+            }
+        };
+
+        let name = Rc::new(format!("{}:{}", pos.filename, pos.line));
+        match parser::parse(name, &text, |doc| ast::process(Default::default(), doc)) {
+            Ok((doc, warnings)) => {
+                let json = to_json(&doc).to_string();
+                (Ast::Scalar(pos, Tag::NonSpecific, ScalarKind::Quoted, json), warnings)
+            }
+            Err(e) => {
+                let err = Error::validation_error(&pos, format!("Invalid Yaml content: {}", e));
+                (Ast::Scalar(pos, Tag::NonSpecific, ScalarKind::Quoted, text), vec![err])
+            }
+        }
+    }
+
+    fn default(&self, _pos: Pos) -> Option<Ast> {
+        None
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub enum Binary {
+    Base64,
+    Hex,
+}
+
+impl Binary {
+    pub fn decode(&self, text: &str) -> Result<Vec<u8>, String> {
+        let text = text.split_whitespace().collect::<String>();
+        match *self {
+            Binary::Base64 => text.from_base64().map_err(|e| format!("{}", e)),
+            Binary::Hex => text.from_hex().map_err(|e| format!("{}", e)),
+        }
+    }
+}
+
+impl Validator for Binary {
+    fn validate(&self, ast: Ast) -> (Ast, Vec<Error>) {
+        let pos = ast.pos();
+        let text = match ast {
+            Ast::Scalar(_, _, _, text) => text,
+            ast => {
+                let message = format!("{:?} content must be a string", self);
+                let err = Error::validation_error(&pos, message);
+                return (ast, vec![err]);
+            }
+        };
+
+        let errors = match self.decode(&text) {
+            Ok(_) => Vec::new(),
+            Err(e) => {
+                let message = format!("Invalid {:?} content: {}", self, e);
+                vec![Error::validation_error(&pos, message)]
+            }
+        };
+        (Ast::Scalar(pos, Tag::NonSpecific, ScalarKind::Quoted, text), errors)
+    }
+
+    fn default(&self, _pos: Pos) -> Option<Ast> {
+        None
+    }
+}
+
+pub fn to_json(ast: &Ast) -> Json {
+    match *ast {
+        Ast::Map(_, _, ref map) => {
+            Json::Object(map.iter().map(|(k, v)| (k.clone(), to_json(v))).collect())
+        }
+        Ast::List(_, _, ref list) => Json::Array(list.iter().map(to_json).collect()),
+        Ast::Null(..) => Json::Null,
+        Ast::Scalar(_, _, ScalarKind::Quoted, ref value) => Json::String(value.clone()),
+        Ast::Scalar(_, _, ScalarKind::Plain, ref value) => match &value[..] {
+            "~" | "null" => Json::Null,
+            "true" => Json::Boolean(true),
+            "false" => Json::Boolean(false),
+            _ => value.parse().map(Json::U64)
+                .or_else(|_| value.parse().map(Json::I64))
+                .ok()
+                .or_else(|| value.parse::<f64>().ok().and_then(|f| {
+                    if f.is_finite() { Some(Json::F64(f)) } else { None }
+                }))
+                .unwrap_or_else(|| Json::String(value.clone())),
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::rc::Rc;
+
+    use quire::ast;
+    use quire::parser;
+    use super::to_json;
+
+    #[test]
+    fn yaml_to_json() {
+        let yaml = "name: \"007\"\nid: 007\nitems: [1, -2, 2.5, true, ~, text]\n";
+        let (doc, _) = parser::parse(Rc::new("test".to_owned()), yaml,
+                                     |doc| ast::process(Default::default(), doc)).unwrap();
+        assert_eq!(to_json(&doc).to_string(),
+                   r#"{"id":7,"items":[1,-2,2.5,true,null,"text"],"name":"007"}"#);
+    }
+}
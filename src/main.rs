@@ -14,16 +14,18 @@ use std::net::SocketAddr;
 use std::path::Path;
 use std::process;
 
-use clap::{App, Arg, Format};
+use clap::{App, Arg, Format, SubCommand};
 use log::{LogRecord, LogLevel, LogLevelFilter};
 use env_logger::LogBuilder;
 use chrono::offset::local::Local as LocalTime;
 
 use responder::Context;
-use responder::server;
+use responder::har;
+use responder::server::{self, UNIX_PREFIX};
 use responder::context::DEFAULT_ADDR;
 
 const DEFAULT_CONFIG: &'static str = "responder.yaml";
+const DEFAULT_IMPORT_DIR: &'static str = "imported";
 
 fn main() {
     match setup_logger() {
@@ -50,7 +52,7 @@ fn main() {
             .short("b")
             .long("bind")
             .value_name("ADDRESS")
-            .help("Address to bind server to")
+            .help("Address to bind server to, or unix:PATH")
             .validator(address_validator)
             .default_value(DEFAULT_ADDR)
             .display_order(2))
@@ -59,8 +61,37 @@ fn main() {
             .long("reload")
             .help("Reload configuration file on every request")
             .display_order(3))
+        .subcommand(SubCommand::with_name("import-har")
+            .about("Converts the requests of a HAR file to a configuration")
+            .arg(Arg::with_name("har")
+                .value_name("HAR_FILE")
+                .help("HAR file exported by a browser")
+                .required(true)
+                .index(1))
+            .arg(Arg::with_name("output")
+                .short("o")
+                .long("output")
+                .value_name("DIR")
+                .help("Directory where the configuration is written")
+                .default_value(DEFAULT_IMPORT_DIR)))
         .get_matches();
 
+    if let Some(matches) = matches.subcommand_matches("import-har") {
+        let har_file = matches.value_of("har").map(|h| Path::new(h)).unwrap();
+        let output = matches.value_of("output").map(|o| Path::new(o)).unwrap();
+
+        match har::import(har_file, output) {
+            Ok(count) => info!("Imported {} routes to {}", count,
+                               output.join(DEFAULT_CONFIG).display()),
+            Err(e) => {
+                write!(io::stderr(), "{} {}\n", Format::Error("error:"), e)
+                    .expect("Unknown error");
+                process::exit(1);
+            }
+        }
+        return;
+    }
+
     let address = if matches.occurrences_of("bind") > 0 {
         matches.value_of("bind")
     } else {
@@ -106,6 +137,9 @@ fn setup_logger() -> Result<(), String> {
 }
 
 fn address_validator(arg: String) -> Result<(), String> {
+    if arg.starts_with(UNIX_PREFIX) && arg.len() > UNIX_PREFIX.len() {
+        return Ok(());
+    }
     arg.parse::<SocketAddr>()
         .map(|_| ())
         .map_err(|_| String::from("invalid adrress"))
@@ -114,14 +148,24 @@ fn address_validator(arg: String) -> Result<(), String> {
 fn run_server(address: Option<&str>, config_file: &Path, reload: bool)
     -> Result<(), String>
 {
-    let context = try!(Context::from_config_file(config_file, reload));
+    let mut context = try!(Context::from_config_file(config_file, reload));
 
-    let address = address.or(Some(context.address()))
-        .unwrap_or(DEFAULT_ADDR)
-        .to_owned();
+    if let Some(address) = address {
+        if context.listeners().len() > 1 {
+            return Err("--bind cannot be used with several listeners".to_owned());
+        }
+        context.set_address(address);
+    }
 
-    info!("Starting http server at http://{}/", &address);
+    for listener in context.listeners() {
+        let scheme = if listener.tls().is_some() { "https" } else { "http" };
+        if listener.address().starts_with(UNIX_PREFIX) {
+            info!("Starting {} server at {}", scheme, listener.address());
+        } else {
+            info!("Starting {} server at {}://{}/", scheme, scheme, listener.address());
+        }
+    }
 
-    try!(server::run(context, &address));
+    try!(server::run_listeners(context));
     Ok(())
 }
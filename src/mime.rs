@@ -0,0 +1,134 @@
+use std::collections::BTreeMap;
+use std::path::Path;
+
+pub const DEFAULT_MIME_TYPE: &'static str = "application/octet-stream";
+
+static MIME_TYPES: &'static [(&'static str, &'static str)] = &[
+    ("css", "text/css"),
+    ("csv", "text/csv"),
+    ("gif", "image/gif"),
+    ("gz", "application/gzip"),
+    ("htm", "text/html"),
+    ("html", "text/html"),
+    ("ico", "image/x-icon"),
+    ("jpeg", "image/jpeg"),
+    ("jpg", "image/jpeg"),
+    ("js", "application/javascript"),
+    ("json", "application/json"),
+    ("mp3", "audio/mpeg"),
+    ("mp4", "video/mp4"),
+    ("pdf", "application/pdf"),
+    ("png", "image/png"),
+    ("svg", "image/svg+xml"),
+    ("txt", "text/plain"),
+    ("wasm", "application/wasm"),
+    ("webm", "video/webm"),
+    ("webp", "image/webp"),
+    ("woff", "font/woff"),
+    ("woff2", "font/woff2"),
+    ("xml", "application/xml"),
+    ("yaml", "application/x-yaml"),
+    ("yml", "application/x-yaml"),
+    ("zip", "application/zip"),
+];
+
+static TEXT_TYPES: &'static [&'static str] = &[
+    "application/javascript",
+    "application/xml",
+    "application/x-yaml",
+    "image/svg+xml",
+];
+
+static COMPRESSED_TYPES: &'static [&'static str] = &[
+    "application/gzip",
+    "application/pdf",
+    "application/zip",
+    "font/woff",
+    "font/woff2",
+];
+
+#[derive(Clone, Debug)]
+pub struct MimeTypes {
+    overrides: BTreeMap<String, String>,
+    charset: String,
+}
+
+impl MimeTypes {
+    pub fn new(overrides: &BTreeMap<String, String>, charset: &str) -> Self {
+        MimeTypes {
+            overrides: overrides.iter()
+                .map(|(ext, mime)| (ext.trim_left_matches('.').to_ascii_lowercase(), mime.clone()))
+                .collect(),
+            charset: charset.to_owned(),
+        }
+    }
+
+    pub fn content_type(&self, path: &Path) -> Option<String> {
+        let ext = path.extension()
+            .and_then(|ext| ext.to_str())
+            .map(|ext| ext.to_ascii_lowercase());
+
+        ext.as_ref()
+            .and_then(|ext| self.overrides.get(ext).map(|m| &**m).or_else(|| guess(ext)))
+            .map(|mime| {
+                if !self.charset.is_empty() && is_text(mime) {
+                    format!("{}; charset={}", mime, self.charset)
+                } else {
+                    mime.to_owned()
+                }
+            })
+    }
+}
+
+pub fn guess(ext: &str) -> Option<&'static str> {
+    MIME_TYPES.iter()
+        .find(|&&(e, _)| e.eq_ignore_ascii_case(ext))
+        .map(|&(_, mime)| mime)
+}
+
+pub fn is_compressed(content_type: &str) -> bool {
+    let mime = content_type.split(';').next().unwrap_or("").trim().to_ascii_lowercase();
+    COMPRESSED_TYPES.contains(&&mime[..]) ||
+        (mime.starts_with("image/") && mime != "image/svg+xml") ||
+        mime.starts_with("audio/") || mime.starts_with("video/")
+}
+
+fn is_text(mime: &str) -> bool {
+    !mime.contains(';') && (mime.starts_with("text/") || TEXT_TYPES.contains(&mime))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+    use std::path::Path;
+
+    use super::{is_compressed, MimeTypes};
+
+    #[test]
+    fn content_type_from_extension() {
+        let mut overrides = BTreeMap::new();
+        overrides.insert(".md".to_owned(), "text/markdown".to_owned());
+        overrides.insert("png".to_owned(), "image/x-png".to_owned());
+        let types = MimeTypes::new(&overrides, "utf-8");
+
+        let content_type = |path| types.content_type(Path::new(path));
+        assert_eq!(content_type("a/index.HTML"), Some("text/html; charset=utf-8".to_owned()));
+        assert_eq!(content_type("data.json"), Some("application/json".to_owned()));
+        assert_eq!(content_type("README.md"), Some("text/markdown; charset=utf-8".to_owned()));
+        assert_eq!(content_type("image.png"), Some("image/x-png".to_owned()));
+        assert_eq!(content_type("archive"), None);
+        assert_eq!(MimeTypes::new(&BTreeMap::new(), "").content_type(Path::new("a.txt")),
+                   Some("text/plain".to_owned()));
+    }
+
+    #[test]
+    fn compressed_types() {
+        assert!(is_compressed("image/png"));
+        assert!(is_compressed("video/mp4"));
+        assert!(is_compressed("application/gzip"));
+        assert!(is_compressed("Application/Zip; foo=bar"));
+        assert!(!is_compressed("image/svg+xml; charset=utf-8"));
+        assert!(!is_compressed("application/json"));
+        assert!(!is_compressed("text/html"));
+    }
+}
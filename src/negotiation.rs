@@ -0,0 +1,82 @@
+use super::request::Request;
+
+pub fn select<'a, I>(req: &Request, media_types: I) -> Option<usize>
+    where I: IntoIterator<Item = &'a str>
+{
+    let accept = match req.header_str("Accept") {
+        Some(accept) if !accept.is_empty() => parse(accept),
+        _ => return media_types.into_iter().next().map(|_| 0),
+    };
+
+    let mut selected = None;
+    let mut best = 0.0;
+    for (i, media_type) in media_types.into_iter().enumerate() {
+        let q = quality(&accept, media_type);
+        if q > best {
+            selected = Some(i);
+            best = q;
+        }
+    }
+    selected
+}
+
+fn parse(accept: &str) -> Vec<(String, f32)> {
+    accept.split(',')
+        .filter_map(|item| {
+            let mut params = item.split(';').map(|p| p.trim());
+            let range = params.next().unwrap_or("").to_ascii_lowercase();
+            if range.is_empty() {
+                return None;
+            }
+            let q = params
+                .filter_map(|p| if p.starts_with("q=") { p[2..].parse().ok() } else { None })
+                .next()
+                .unwrap_or(1.0);
+            Some((range, q))
+        })
+        .collect()
+}
+
+fn quality(accept: &[(String, f32)], media_type: &str) -> f32 {
+    let media_type = media_type.split(';').next().unwrap_or("").trim().to_ascii_lowercase();
+    let main_type = media_type.split('/').next().unwrap_or("");
+
+    let mut specificity = 0;
+    let mut quality = 0.0;
+    for &(ref range, q) in accept.iter() {
+        let matched = if *range == media_type {
+            3
+        } else if range.ends_with("/*") && range[..range.len() - 2] == *main_type {
+            2
+        } else if range == "*/*" {
+            1
+        } else {
+            0
+        };
+
+        if matched > specificity {
+            specificity = matched;
+            quality = q;
+        }
+    }
+    quality
+}
+
+#[cfg(test)]
+mod tests {
+    use request::with_header;
+    use super::select;
+
+    #[test]
+    fn select_media_type() {
+        let types = ["application/json", "application/xml", "text/csv"];
+        let select = |accept| select(&with_header("GET", "Accept", accept), types.iter().cloned());
+
+        assert_eq!(select(""), Some(0));
+        assert_eq!(select("text/csv"), Some(2));
+        assert_eq!(select("application/xml;q=0.9, application/json;q=0.5"), Some(1));
+        assert_eq!(select("text/*, application/*;q=0.2"), Some(2));
+        assert_eq!(select("*/*;q=0.1, application/json;q=0"), Some(1));
+        assert_eq!(select("text/html"), None);
+    }
+}
@@ -0,0 +1,512 @@
+use std::cmp;
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+use std::rc::Rc;
+use std::str;
+use std::sync::Arc;
+
+use quire::ast;
+use quire::parser;
+use regex::{self, Regex};
+use rustc_serialize::json::Json;
+
+use super::directory::percent_decode;
+use super::handler::{Body, Handler};
+use super::literal;
+use super::request::Request;
+use super::schema;
+
+const METHODS: [&'static str; 8] = ["get", "put", "post", "delete", "options", "head", "patch",
+                                     "trace"];
+
+const MAX_REFS: usize = 32;
+
+#[derive(Debug)]
+pub struct Example {
+    pub media_type: String,
+    pub value: Json,
+}
+
+impl Example {
+    pub fn body(&self, pretty: bool) -> Vec<u8> {
+        match self.value {
+            Json::String(ref text) if !is_json(&self.media_type) => text.as_bytes().to_owned(),
+            ref value if pretty => value.pretty().to_string().into_bytes(),
+            ref value => value.to_string().into_bytes(),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct Operation {
+    pub method: String,
+    pub template: String,
+    pub path: String,
+    pub status: u16,
+    pub headers: Vec<(String, String)>,
+    pub examples: Vec<Example>,
+    pub parameters: Vec<Json>,
+    pub request_body: Option<Json>,
+}
+
+pub fn read_spec(path: &Path) -> Result<Json, String> {
+    let mut text = String::new();
+    try!(File::open(path)
+        .and_then(|mut file| file.read_to_string(&mut text))
+        .map_err(|e| format!("Error reading {}: {}", path.display(), e)));
+
+    if text.trim_left().starts_with('{') {
+        return Json::from_str(&text)
+            .map_err(|e| format!("Invalid document {}: {}", path.display(), e));
+    }
+
+    let name = Rc::new(path.display().to_string());
+    parser::parse(name, &text, |doc| ast::process(Default::default(), doc))
+        .map(|(doc, _)| literal::to_json(&doc))
+        .map_err(|e| format!("Invalid document {}: {}", path.display(), e))
+}
+
+pub fn operations(spec: &Json) -> Result<Vec<Operation>, String> {
+    let paths = try!(spec.find("paths").and_then(|p| p.as_object())
+        .ok_or("OpenAPI document without paths".to_owned()));
+
+    let mut templates: Vec<&String> = paths.keys().collect();
+    templates.sort_by_key(|t| t.contains('{'));
+
+    let mut operations = Vec::new();
+    for template in templates {
+        let item = try!(resolve(spec, &paths[template]));
+        for method in METHODS.iter() {
+            let operation = match item.find(method) {
+                Some(operation) => try!(resolve(spec, operation)),
+                None => continue,
+            };
+            let (status, response) = match try!(select_response(spec, operation)) {
+                Some(response) => response,
+                None => (200, None),
+            };
+
+            let mut parameters: Vec<Json> = Vec::new();
+            let shared = item.find("parameters").and_then(|p| p.as_array());
+            let own = operation.find("parameters").and_then(|p| p.as_array());
+            for parameter in shared.iter().chain(own.iter()).flat_map(|p| p.iter()) {
+                let parameter = try!(resolve(spec, parameter)).clone();
+                // parameters of the operation override the ones of the path
+                parameters.retain(|p| p.find("name") != parameter.find("name") ||
+                                      p.find("in") != parameter.find("in"));
+                parameters.push(parameter);
+            }
+            let request_body = match operation.find("requestBody") {
+                Some(body) => Some(try!(resolve(spec, body)).clone()),
+                None => None,
+            };
+
+            operations.push(Operation {
+                method: method.to_uppercase(),
+                template: template.clone(),
+                path: path_regex(template),
+                status: status,
+                headers: match response {
+                    Some(response) => try!(headers(spec, response)),
+                    None => Vec::new(),
+                },
+                examples: match response {
+                    Some(response) => try!(examples(spec, response)),
+                    None => Vec::new(),
+                },
+                parameters: parameters,
+                request_body: request_body,
+            });
+        }
+    }
+    Ok(operations)
+}
+
+pub fn path_regex(template: &str) -> String {
+    let mut path = String::new();
+    let mut rest = template;
+    while let Some(start) = rest.find('{') {
+        let end = match rest[start..].find('}') {
+            Some(end) => start + end,
+            None => break,
+        };
+        path.push_str(&regex::quote(&rest[..start]));
+        path.push_str(&format!("(?P<{}>[^/?]+)", group_name(&rest[start + 1..end])));
+        rest = &rest[end + 1..];
+    }
+    path.push_str(&regex::quote(rest));
+    path
+}
+
+fn group_name(name: &str) -> String {
+    let name: String = name.chars()
+        .map(|c| if c.is_alphanumeric() || c == '_' { c } else { '_' })
+        .collect();
+    if name.starts_with(|c: char| !c.is_alphabetic() && c != '_') || name.is_empty() {
+        format!("_{}", name)
+    } else {
+        name
+    }
+}
+
+pub fn resolve<'a>(spec: &'a Json, value: &'a Json) -> Result<&'a Json, String> {
+    let mut value = value;
+    for _ in 0..MAX_REFS {
+        let reference = match value.find("$ref").and_then(|r| r.as_string()) {
+            Some(reference) => reference,
+            None => return Ok(value),
+        };
+        if !reference.starts_with("#/") {
+            return Err(format!("Unsupported reference {}, only local ones are allowed",
+                               reference));
+        }
+
+        let keys: Vec<String> = reference[2..].split('/')
+            .map(|k| k.replace("~1", "/").replace("~0", "~"))
+            .collect();
+        let keys: Vec<&str> = keys.iter().map(|k| &k[..]).collect();
+        value = try!(spec.find_path(&keys).ok_or(format!("Unknown reference {}", reference)));
+    }
+    Err("Too many nested references".to_owned())
+}
+
+fn select_response<'a>(spec: &'a Json, operation: &'a Json)
+    -> Result<Option<(u16, Option<&'a Json>)>, String>
+{
+    let responses = match operation.find("responses").and_then(|r| r.as_object()) {
+        Some(responses) => responses,
+        None => return Ok(None),
+    };
+
+    let status = |key: &str| -> Option<u16> {
+        match key {
+            "default" => Some(200),
+            "2XX" | "2xx" => Some(200),
+            key => key.parse().ok(),
+        }
+    };
+    let success = responses.keys()
+        .filter_map(|k| status(k).map(|s| (s, k)))
+        .filter(|&(s, _)| s >= 200 && s < 300)
+        .min_by_key(|&(s, k)| (s, k == "default"));
+
+    let (status, key) = match success.or_else(|| {
+        responses.keys().filter_map(|k| status(k).map(|s| (s, k))).next()
+    }) {
+        Some(selected) => selected,
+        None => return Ok(None),
+    };
+    Ok(Some((status, Some(try!(resolve(spec, &responses[key]))))))
+}
+
+fn examples(spec: &Json, response: &Json) -> Result<Vec<Example>, String> {
+    let content = match response.find("content").and_then(|c| c.as_object()) {
+        Some(content) => content,
+        None => return Ok(Vec::new()),
+    };
+
+    let mut examples = Vec::new();
+    for (media_type, media) in content.iter() {
+        let media = try!(resolve(spec, media));
+        if let Some(value) = try!(example(spec, media)) {
+            examples.push(Example {
+                media_type: media_type.clone(),
+                value: value.clone(),
+            });
+        }
+    }
+    examples.sort_by_key(|e| !is_json(&e.media_type));
+    Ok(examples)
+}
+
+fn example<'a>(spec: &'a Json, object: &'a Json) -> Result<Option<&'a Json>, String> {
+    if let Some(example) = object.find("example") {
+        return Ok(Some(example));
+    }
+    if let Some(example) = object.find("examples").and_then(|e| e.as_object())
+        .and_then(|e| e.values().next())
+    {
+        return Ok(try!(resolve(spec, example)).find("value"));
+    }
+    match object.find("schema") {
+        Some(schema) => Ok(try!(resolve(spec, schema)).find("example")),
+        None => Ok(None),
+    }
+}
+
+fn headers(spec: &Json, response: &Json) -> Result<Vec<(String, String)>, String> {
+    let headers = match response.find("headers").and_then(|h| h.as_object()) {
+        Some(headers) => headers,
+        None => return Ok(Vec::new()),
+    };
+
+    let mut values = Vec::new();
+    for (name, header) in headers.iter() {
+        let header = try!(resolve(spec, header));
+        match try!(example(spec, header)) {
+            Some(&Json::String(ref value)) => values.push((name.clone(), value.clone())),
+            Some(value) => values.push((name.clone(), value.to_string())),
+            None => {}
+        }
+    }
+    Ok(values)
+}
+
+#[derive(Debug)]
+pub struct Validation {
+    spec: Arc<Json>,
+    route: Regex,
+    parameters: Vec<Json>,
+    request_body: Option<Json>,
+    status: u16,
+}
+
+impl Validation {
+    pub fn new(spec: Arc<Json>, route: Regex, operation: &Operation, status: u16) -> Self {
+        Validation {
+            spec: spec,
+            route: route,
+            parameters: operation.parameters.clone(),
+            request_body: operation.request_body.clone(),
+            status: status,
+        }
+    }
+
+    pub fn check(&self, req: &Request, body: &[u8]) -> Result<(), Vec<String>> {
+        let mut errors = Vec::new();
+        for parameter in self.parameters.iter() {
+            self.check_parameter(parameter, req, &mut errors);
+        }
+        if let Some(ref request_body) = self.request_body {
+            self.check_body(request_body, req, body, &mut errors);
+        }
+
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors)
+        }
+    }
+
+    pub fn invalid(&self, errors: &[String]) -> Handler {
+        let mut object = BTreeMap::new();
+        object.insert("errors".to_owned(),
+                      Json::Array(errors.iter().map(|e| Json::String(e.clone())).collect()));
+
+        let mut handler = Handler::new(self.status);
+        handler.set_content(Some(Body::Data(Json::Object(object).to_string().into_bytes())));
+        handler.add_header("Content-Type".to_owned(), b"application/json".to_vec());
+        handler
+    }
+
+    fn check_parameter(&self, parameter: &Json, req: &Request, errors: &mut Vec<String>) {
+        let name = parameter.find("name").and_then(|n| n.as_string()).unwrap_or("");
+        let location = parameter.find("in").and_then(|i| i.as_string()).unwrap_or("");
+
+        let values: Vec<String> = match location {
+            "path" => {
+                self.route.captures(&req.path)
+                    .and_then(|c| c.name(&group_name(name)).and_then(percent_decode))
+                    .into_iter().collect()
+            }
+            "query" => {
+                let query = req.path.splitn(2, '?').nth(1).unwrap_or("");
+                query.split('&')
+                    .filter_map(|pair| {
+                        let mut pair = pair.splitn(2, '=');
+                        let key = pair.next().map(|k| k.replace('+', " "))
+                            .and_then(|k| percent_decode(&k));
+                        let value = pair.next().unwrap_or("").replace('+', " ");
+                        match key {
+                            Some(ref key) if key == name => percent_decode(&value),
+                            _ => None,
+                        }
+                    })
+                    .collect()
+            }
+            "header" => req.header_str(name).map(|v| v.to_owned()).into_iter().collect(),
+            "cookie" => req.header_str("Cookie").into_iter()
+                .flat_map(|c| c.split(';'))
+                .filter_map(|cookie| {
+                    let mut cookie = cookie.trim().splitn(2, '=');
+                    match (cookie.next(), cookie.next()) {
+                        (Some(key), Some(value)) if key == name => Some(value.to_owned()),
+                        _ => None,
+                    }
+                })
+                .collect(),
+            _ => return,
+        };
+
+        let required = location == "path" ||
+            parameter.find("required").and_then(|r| r.as_boolean()).unwrap_or(false);
+        if values.is_empty() {
+            if required {
+                errors.push(format!("missing required {} parameter {}", location, name));
+            }
+            return;
+        }
+
+        if let Some(param_schema) = parameter.find("schema") {
+            let value = coerce(&self.spec, param_schema, &values, 0);
+            schema::validate(&self.spec, param_schema, &value,
+                             &format!("{} parameter {}", location, name), errors);
+        }
+    }
+
+    fn check_body(&self, request_body: &Json, req: &Request, body: &[u8], errors: &mut Vec<String>) {
+        if body.is_empty() {
+            if request_body.find("required").and_then(|r| r.as_boolean()).unwrap_or(false) {
+                errors.push("missing required request body".to_owned());
+            }
+            return;
+        }
+
+        let content = match request_body.find("content").and_then(|c| c.as_object()) {
+            Some(content) => content,
+            None => return,
+        };
+        let content_type = match req.header_str("Content-Type") {
+            Some(content_type) => {
+                content_type.split(';').next().unwrap_or("").trim().to_ascii_lowercase()
+            }
+            None => {
+                errors.push("missing Content-Type of the request body".to_owned());
+                return;
+            }
+        };
+
+        let media = content.iter()
+            .find(|&(range, _)| media_range_matches(range, &content_type))
+            .map(|(_, media)| media);
+        let media = match media {
+            Some(media) => media,
+            None => {
+                let expected: Vec<&str> = content.keys().map(|k| &k[..]).collect();
+                errors.push(format!("unsupported content type {}, expected {}", content_type,
+                                    expected.join(", ")));
+                return;
+            }
+        };
+
+        let body_schema = match resolve(&self.spec, media).ok().and_then(|m| m.find("schema")) {
+            Some(body_schema) if is_json(&content_type) => body_schema,
+            _ => return,
+        };
+        match str::from_utf8(body).map_err(|e| e.to_string())
+            .and_then(|body| if nesting(body) > schema::MAX_DEPTH {
+                Err("nested too deeply".to_owned())
+            } else {
+                Json::from_str(body).map_err(|e| e.to_string())
+            })
+        {
+            Ok(value) => schema::validate(&self.spec, body_schema, &value, "body", errors),
+            Err(e) => errors.push(format!("invalid JSON body: {}", e)),
+        }
+    }
+}
+
+/// The JSON parser recurses for each level, so deep bodies are rejected before parsing
+fn nesting(text: &str) -> usize {
+    let mut depth: usize = 0;
+    let mut max = 0;
+    let mut string = false;
+    let mut escaped = false;
+    for c in text.bytes() {
+        match c {
+            _ if escaped => escaped = false,
+            b'\\' if string => escaped = true,
+            b'"' => string = !string,
+            b'[' | b'{' if !string => {
+                depth += 1;
+                max = cmp::max(max, depth);
+            }
+            b']' | b'}' if !string => depth = depth.saturating_sub(1),
+            _ => {}
+        }
+    }
+    max
+}
+
+fn coerce(spec: &Json, param_schema: &Json, values: &[String], depth: usize) -> Json {
+    let param_schema = resolve(spec, param_schema).unwrap_or(param_schema);
+    let kind = param_schema.find("type").and_then(|t| t.as_string()).unwrap_or("string");
+
+    if kind == "array" && depth < schema::MAX_DEPTH {
+        // a single value holds the items separated by commas
+        let items: Vec<&str> = if values.len() == 1 {
+            values[0].split(',').collect()
+        } else {
+            values.iter().map(|v| &v[..]).collect()
+        };
+        let item_schema = param_schema.find("items").cloned().unwrap_or(Json::Null);
+        return Json::Array(items.iter()
+            .map(|item| coerce(spec, &item_schema, &[item.to_string()], depth + 1))
+            .collect());
+    }
+
+    let text = &values[0];
+    let value = match kind {
+        "integer" | "number" => text.parse().map(Json::U64).ok()
+            .or_else(|| text.parse().map(Json::I64).ok())
+            .or_else(|| text.parse().map(Json::F64).ok()),
+        "boolean" => text.parse().map(Json::Boolean).ok(),
+        _ => None,
+    };
+    value.unwrap_or_else(|| Json::String(text.clone()))
+}
+
+fn media_range_matches(range: &str, media_type: &str) -> bool {
+    let range = range.split(';').next().unwrap_or("").trim().to_ascii_lowercase();
+    range == "*/*" || range == media_type ||
+        (range.ends_with("/*") && media_type.starts_with(&range[..range.len() - 1]))
+}
+
+fn is_json(media_type: &str) -> bool {
+    let media_type = media_type.split(';').next().unwrap_or("").trim();
+    media_type == "application/json" || media_type.ends_with("+json")
+}
+
+#[cfg(test)]
+mod tests {
+    use rustc_serialize::json::Json;
+    use super::{nesting, operations, path_regex};
+
+    #[test]
+    fn path_template_regex() {
+        assert_eq!(path_regex("/pets/{petId}/photos"), r"/pets/(?P<petId>[^/?]+)/photos");
+        assert_eq!(path_regex("/files/{file-name}.json"), r"/files/(?P<file_name>[^/?]+)\.json");
+        assert_eq!(path_regex("/v/{1}"), r"/v/(?P<_1>[^/?]+)");
+    }
+
+    #[test]
+    fn json_nesting() {
+        assert_eq!(nesting(r#"{"a": [1, {"b": "]}[{"}], "c": "\"["}"#), 3);
+        assert_eq!(nesting("7"), 0);
+    }
+
+    #[test]
+    fn operation_examples() {
+        let spec = Json::from_str(r##"{"paths": {
+            "/pets/{id}": {"get": {"responses": {
+                "404": {"description": "Not found"},
+                "200": {"$ref": "#/components/responses/Pet"}}}},
+            "/pets": {"post": {"responses": {"default": {"description": "Created"}}}}},
+            "components": {"responses": {"Pet": {"content": {
+                "text/plain": {"example": "Rex"},
+                "application/json": {"examples": {"rex": {"value": {"name": "Rex"}}}}}}}}
+        }"##).unwrap();
+
+        let operations = operations(&spec).unwrap();
+        assert_eq!(operations.len(), 2);
+        assert_eq!((&operations[0].method[..], operations[0].status), ("POST", 200));
+
+        let examples = &operations[1].examples;
+        assert_eq!(operations[1].status, 200);
+        assert_eq!(examples[0].body(false), br#"{"name":"Rex"}"#.to_vec());
+        assert_eq!((&examples[1].media_type[..], examples[1].body(false)),
+                   ("text/plain", b"Rex".to_vec()));
+    }
+}
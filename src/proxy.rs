@@ -0,0 +1,409 @@
+use std::collections::VecDeque;
+use std::fmt;
+use std::io::{Read, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::str;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use httparse;
+use rotor::Notifier;
+use rotor_http::server::Response;
+
+use super::request::{Headers, Request};
+
+const TIMEOUT: u64 = 30;
+
+const MAX_WORKERS: usize = 16;
+
+const HOP_BY_HOP: &'static [&'static str] = &[
+    "Connection", "Keep-Alive", "Proxy-Authenticate", "Proxy-Authorization", "TE",
+    "Trailer", "Transfer-Encoding", "Upgrade",
+];
+
+#[derive(Clone, Debug)]
+pub struct Upstream {
+    host: String,
+    port: u16,
+    prefix: String,
+    workers: Arc<Mutex<Workers>>,
+}
+
+struct Job {
+    req: Request,
+    body: Vec<u8>,
+    slot: Arc<Mutex<Option<Result<UpstreamResponse, String>>>>,
+    notifier: Notifier,
+}
+
+#[derive(Default)]
+struct Workers {
+    jobs: VecDeque<Job>,
+    running: usize,
+}
+
+impl fmt::Debug for Workers {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Workers {{ jobs: {}, running: {} }}", self.jobs.len(), self.running)
+    }
+}
+
+impl Upstream {
+    pub fn parse(url: &str) -> Result<Self, String> {
+        if !url.starts_with("http://") {
+            return Err(format!("Invalid proxy URL {}, only http is supported", url));
+        }
+
+        let rest = &url["http://".len()..];
+        let (authority, prefix) = match rest.find('/') {
+            Some(i) => (&rest[..i], &rest[i..]),
+            None => (rest, ""),
+        };
+        let (host, port) = if authority.starts_with('[') {
+            let end = try!(authority.find(']')
+                .ok_or(format!("Invalid host in proxy URL {}", url)));
+            match &authority[end + 1..] {
+                "" => (&authority[1..end], None),
+                port if port.starts_with(':') => (&authority[1..end], Some(&port[1..])),
+                _ => return Err(format!("Invalid host in proxy URL {}", url)),
+            }
+        } else {
+            match authority.rfind(':') {
+                Some(i) => (&authority[..i], Some(&authority[i + 1..])),
+                None => (authority, None),
+            }
+        };
+        let port = match port {
+            Some(port) => try!(port.parse()
+                .map_err(|_| format!("Invalid port in proxy URL {}", url))),
+            None => 80,
+        };
+        if host.is_empty() {
+            return Err(format!("Missing host in proxy URL {}", url));
+        }
+
+        Ok(Upstream {
+            host: host.to_owned(),
+            port: port,
+            prefix: prefix.trim_right_matches('/').to_owned(),
+            workers: Arc::new(Mutex::new(Workers::default())),
+        })
+    }
+
+    pub fn forward(&self, req: Request, body: Vec<u8>, notifier: Notifier) -> Pending {
+        let pending = Pending(Arc::new(Mutex::new(None)));
+        let spawn = {
+            let mut workers = self.workers.lock().unwrap();
+            workers.jobs.push_back(Job {
+                req: req,
+                body: body,
+                slot: pending.0.clone(),
+                notifier: notifier,
+            });
+            if workers.running < MAX_WORKERS {
+                workers.running += 1;
+                true
+            } else {
+                false
+            }
+        };
+        if spawn {
+            let upstream = self.clone();
+            thread::spawn(move || upstream.work());
+        }
+        pending
+    }
+
+    fn work(&self) {
+        loop {
+            let job = {
+                let mut workers = self.workers.lock().unwrap();
+                match workers.jobs.pop_front() {
+                    Some(job) => job,
+                    None => {
+                        workers.running -= 1;
+                        return;
+                    }
+                }
+            };
+            let result = self.send(&job.req, &job.body);
+            *job.slot.lock().unwrap() = Some(result);
+            if let Err(e) = job.notifier.wakeup() {
+                error!("Error waking up proxied request: {:?}", e);
+            }
+        }
+    }
+
+    fn connect(&self) -> Result<TcpStream, String> {
+        let timeout = Duration::new(TIMEOUT, 0);
+        let addresses = try!((&self.host[..], self.port).to_socket_addrs()
+            .map_err(|e| format!("Error resolving {}: {}", self, e)));
+        let mut error = format!("Error resolving {}: no addresses", self);
+        for address in addresses {
+            match TcpStream::connect_timeout(&address, timeout) {
+                Ok(stream) => return Ok(stream),
+                Err(e) => error = format!("Error connecting to {}: {}", self, e),
+            }
+        }
+        Err(error)
+    }
+
+    fn authority(&self) -> String {
+        let host = if self.host.contains(':') {
+            format!("[{}]", self.host)
+        } else {
+            self.host.clone()
+        };
+        if self.port == 80 {
+            host
+        } else {
+            format!("{}:{}", host, self.port)
+        }
+    }
+
+    pub fn send(&self, req: &Request, body: &[u8]) -> Result<UpstreamResponse, String> {
+        let mut stream = try!(self.connect());
+        let timeout = Some(Duration::new(TIMEOUT, 0));
+        try!(stream.set_read_timeout(timeout)
+            .and_then(|_| stream.set_write_timeout(timeout))
+            .map_err(|e| format!("{}", e)));
+
+        let mut data = Vec::new();
+        write!(data, "{} {}{} HTTP/1.1\r\n", req.method, self.prefix, req.path).unwrap();
+        write!(data, "Host: {}\r\n", self.authority()).unwrap();
+        let tokens = connection_tokens(&req.headers);
+        for &(ref name, ref value) in req.headers.iter() {
+            if is_hop_by_hop(name, &tokens) || ["Host", "Content-Length", "Expect"].iter()
+                .any(|h| h.eq_ignore_ascii_case(name))
+            {
+                continue;
+            }
+            write!(data, "{}: ", name).unwrap();
+            data.extend_from_slice(value);
+            data.extend_from_slice(b"\r\n");
+        }
+        if !body.is_empty() || req.header("Content-Length").is_some() {
+            write!(data, "Content-Length: {}\r\n", body.len()).unwrap();
+        }
+        data.extend_from_slice(b"Connection: close\r\n\r\n");
+        data.extend_from_slice(body);
+
+        try!(stream.write_all(&data)
+            .map_err(|e| format!("Error sending request to {}: {}", self, e)));
+
+        let mut response = Vec::new();
+        try!(stream.read_to_end(&mut response)
+            .map_err(|e| format!("Error reading response from {}: {}", self, e)));
+
+        parse_response(&response, req.method == "HEAD")
+    }
+}
+
+impl fmt::Display for Upstream {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.host.contains(':') {
+            write!(f, "http://[{}]:{}{}", self.host, self.port, self.prefix)
+        } else {
+            write!(f, "http://{}:{}{}", self.host, self.port, self.prefix)
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct Pending(Arc<Mutex<Option<Result<UpstreamResponse, String>>>>);
+
+impl Pending {
+    pub fn take(&self) -> Option<Result<UpstreamResponse, String>> {
+        self.0.lock().unwrap().take()
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct UpstreamResponse {
+    pub status: u16,
+    pub reason: String,
+    pub headers: Headers,
+    pub body: Vec<u8>,
+}
+
+impl UpstreamResponse {
+    pub fn header(&self, name: &str) -> Option<&[u8]> {
+        self.headers.iter()
+            .find(|&&(ref k, _)| k.eq_ignore_ascii_case(name))
+            .map(|&(_, ref v)| &v[..])
+    }
+
+    pub fn send(&self, req: &Request, res: &mut Response) -> Result<(), String> {
+        if is_interim(Some(self.status)) {
+            return Err(format!("Unexpected interim response {} from upstream", self.status));
+        }
+
+        res.status(self.status, &self.reason);
+        let tokens = connection_tokens(&self.headers);
+        for &(ref name, ref value) in self.headers.iter() {
+            if !is_hop_by_hop(name, &tokens) && !name.eq_ignore_ascii_case("Content-Length") {
+                try!(res.add_header(name, value)
+                    .map_err(|e| format!("Invalid header {} from upstream: {}", name, e)));
+            }
+        }
+
+        if self.status >= 200 && self.status != 204 && self.status != 304 {
+            let length = if req.method == "HEAD" {
+                self.header("Content-Length")
+                    .and_then(|v| str::from_utf8(v).ok())
+                    .and_then(|v| v.trim().parse().ok())
+                    .unwrap_or(0)
+            } else {
+                self.body.len() as u64
+            };
+            try!(res.add_length(length)
+                .map_err(|e| format!("Invalid length from upstream: {}", e)));
+        }
+
+        if try!(res.done_headers().map_err(|e| format!("Invalid upstream response: {}", e))) {
+            res.write_body(&self.body);
+        }
+        res.done();
+        Ok(())
+    }
+}
+
+fn connection_tokens(headers: &Headers) -> Vec<String> {
+    headers.iter()
+        .filter(|&&(ref k, _)| k.eq_ignore_ascii_case("Connection"))
+        .filter_map(|&(_, ref v)| str::from_utf8(v).ok())
+        .flat_map(|v| v.split(','))
+        .map(|t| t.trim().to_owned())
+        .filter(|t| !t.is_empty())
+        .collect()
+}
+
+fn is_hop_by_hop(name: &str, tokens: &[String]) -> bool {
+    HOP_BY_HOP.iter().any(|h| h.eq_ignore_ascii_case(name))
+        || tokens.iter().any(|t| t.eq_ignore_ascii_case(name))
+}
+
+fn is_interim(code: Option<u16>) -> bool {
+    code.map_or(false, |c| c >= 100 && c < 200 && c != 101)
+}
+
+fn parse_response(mut data: &[u8], head: bool) -> Result<UpstreamResponse, String> {
+    loop {
+        let mut headers = [httparse::EMPTY_HEADER; 100];
+        let mut res = httparse::Response::new(&mut headers);
+        match res.parse(data) {
+            Ok(httparse::Status::Complete(size)) if is_interim(res.code) => data = &data[size..],
+            _ => break,
+        }
+    }
+
+    let mut headers = [httparse::EMPTY_HEADER; 100];
+    let mut res = httparse::Response::new(&mut headers);
+    let size = match res.parse(data) {
+        Ok(httparse::Status::Complete(size)) => size,
+        Ok(httparse::Status::Partial) => return Err("Incomplete upstream response".to_owned()),
+        Err(e) => return Err(format!("Invalid upstream response: {:?}", e)),
+    };
+
+    let mut response = UpstreamResponse {
+        status: res.code.unwrap_or(502),
+        reason: res.reason.unwrap_or("").to_owned(),
+        headers: res.headers.iter().map(|h| (h.name.to_owned(), h.value.to_owned())).collect(),
+        body: Vec::new(),
+    };
+
+    let status = response.status;
+    if head || status < 200 || status == 204 || status == 304 {
+        return Ok(response);
+    }
+
+    let body = &data[size..];
+    let chunked = response.header("Transfer-Encoding")
+        .and_then(|v| str::from_utf8(v).ok())
+        .map_or(false, |v| v.to_ascii_lowercase().contains("chunked"));
+    let length = response.header("Content-Length")
+        .and_then(|v| str::from_utf8(v).ok())
+        .and_then(|v| v.trim().parse::<usize>().ok());
+
+    response.body = if chunked {
+        try!(decode_chunked(body))
+    } else if let Some(length) = length {
+        if body.len() < length {
+            return Err("Truncated upstream response".to_owned());
+        }
+        body[..length].to_vec()
+    } else {
+        body.to_vec()
+    };
+    Ok(response)
+}
+
+fn decode_chunked(data: &[u8]) -> Result<Vec<u8>, String> {
+    let mut body = Vec::new();
+    let mut rest = data;
+    loop {
+        let line_end = try!(rest.windows(2).position(|w| w == b"\r\n")
+            .ok_or("Truncated chunked body".to_owned()));
+        let line = String::from_utf8_lossy(&rest[..line_end]).into_owned();
+        let size = try!(usize::from_str_radix(line.split(';').next().unwrap_or("").trim(), 16)
+            .map_err(|_| format!("Invalid chunk size {}", line)));
+        rest = &rest[line_end + 2..];
+
+        if size == 0 {
+            return Ok(body);
+        }
+        if rest.len() < size + 2 {
+            return Err("Truncated chunked body".to_owned());
+        }
+        body.extend_from_slice(&rest[..size]);
+        rest = &rest[size + 2..];
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{connection_tokens, decode_chunked, is_hop_by_hop, parse_response, Upstream};
+
+    #[test]
+    fn parse_upstream_url() {
+        let upstream = Upstream::parse("http://localhost:8080/api/").unwrap();
+        assert_eq!(upstream.to_string(), "http://localhost:8080/api");
+        assert_eq!(Upstream::parse("http://example.com").unwrap().to_string(),
+                   "http://example.com:80");
+        assert!(Upstream::parse("https://example.com").is_err());
+        assert!(Upstream::parse("http://example.com:port").is_err());
+        assert_eq!(Upstream::parse("http://[::1]:8080/api").unwrap().to_string(),
+                   "http://[::1]:8080/api");
+        assert_eq!(Upstream::parse("http://[::1]").unwrap().authority(), "[::1]");
+        assert!(Upstream::parse("http://[::1").is_err());
+        assert!(Upstream::parse("http://[::1]8080").is_err());
+    }
+
+    #[test]
+    fn decode_chunked_body() {
+        let data = b"5\r\nHello\r\n7;ext=1\r\n, world\r\n0\r\nTrailer: x\r\n\r\n";
+        assert_eq!(decode_chunked(data).unwrap(), b"Hello, world".to_vec());
+        assert!(decode_chunked(b"5\r\nHel").is_err());
+        assert!(decode_chunked(b"z\r\n").is_err());
+    }
+
+    #[test]
+    fn skip_interim_responses() {
+        let data = b"HTTP/1.1 100 Continue\r\n\r\nHTTP/1.1 103 Early Hints\r\nLink: </a>\r\n\r\n\
+                     HTTP/1.1 200 OK\r\nContent-Length: 2\r\n\r\nok";
+        let response = parse_response(data, false).unwrap();
+        assert_eq!(response.status, 200);
+        assert_eq!(response.body, b"ok".to_vec());
+        assert!(parse_response(b"HTTP/1.1 102 Processing\r\n\r\n", false).is_err());
+    }
+
+    #[test]
+    fn connection_header_names() {
+        let headers = vec![("Connection".to_owned(), b"close, X-Session".to_vec())];
+        let tokens = connection_tokens(&headers);
+        assert!(is_hop_by_hop("x-session", &tokens));
+        assert!(is_hop_by_hop("Keep-Alive", &tokens));
+        assert!(!is_hop_by_hop("Content-Type", &tokens));
+    }
+}
@@ -0,0 +1,242 @@
+use std::cmp;
+use std::collections::VecDeque;
+use std::io::{self, Cursor, Read, Seek, SeekFrom};
+
+pub const MAX_RANGES: usize = 16;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ByteRange {
+    pub start: u64,
+    pub end: u64,
+}
+
+impl ByteRange {
+    pub fn len(&self) -> u64 {
+        self.end - self.start + 1
+    }
+
+    pub fn content_range(&self, length: u64) -> String {
+        format!("bytes {}-{}/{}", self.start, self.end, length)
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub enum RangeError {
+    Invalid,
+    Unsatisfiable,
+    TooMany,
+}
+
+pub fn parse(header: &str, length: u64) -> Result<Vec<ByteRange>, RangeError> {
+    let header = header.trim();
+    if !header.starts_with("bytes=") {
+        return Err(RangeError::Invalid);
+    }
+
+    let mut ranges = Vec::new();
+    let mut specs = 0;
+    for spec in header["bytes=".len()..].split(',').map(|s| s.trim()).filter(|s| !s.is_empty()) {
+        specs += 1;
+        let (first, last) = match spec.find('-') {
+            Some(pos) => (spec[..pos].trim(), spec[pos + 1..].trim()),
+            None => return Err(RangeError::Invalid),
+        };
+
+        if first.is_empty() {
+            let suffix: u64 = try!(last.parse().map_err(|_| RangeError::Invalid));
+            if suffix > 0 && length > 0 {
+                ranges.push(ByteRange {
+                    start: length.saturating_sub(suffix),
+                    end: length - 1,
+                });
+            }
+            continue;
+        }
+
+        let start: u64 = try!(first.parse().map_err(|_| RangeError::Invalid));
+        let end = if last.is_empty() {
+            None
+        } else {
+            let end: u64 = try!(last.parse().map_err(|_| RangeError::Invalid));
+            if end < start {
+                return Err(RangeError::Invalid);
+            }
+            Some(end)
+        };
+
+        if start < length {
+            ranges.push(ByteRange {
+                start: start,
+                end: end.map(|e| if e < length { e } else { length - 1 }).unwrap_or(length - 1),
+            });
+        }
+    }
+
+    if specs == 0 {
+        Err(RangeError::Invalid)
+    } else if ranges.is_empty() {
+        Err(RangeError::Unsatisfiable)
+    } else {
+        let ranges = coalesce(ranges);
+        if ranges.len() > MAX_RANGES {
+            Err(RangeError::TooMany)
+        } else {
+            Ok(ranges)
+        }
+    }
+}
+
+fn coalesce(mut ranges: Vec<ByteRange>) -> Vec<ByteRange> {
+    ranges.sort_by_key(|r| r.start);
+    let mut merged: Vec<ByteRange> = Vec::with_capacity(ranges.len());
+    for range in ranges {
+        match merged.last_mut() {
+            Some(last) if range.start <= last.end + 1 => {
+                if range.end > last.end {
+                    last.end = range.end;
+                }
+                continue;
+            }
+            _ => {}
+        }
+        merged.push(range);
+    }
+    merged
+}
+
+enum Part {
+    Data(Cursor<Vec<u8>>),
+    Range(ByteRange),
+}
+
+pub struct Multipart<R> {
+    source: R,
+    parts: VecDeque<Part>,
+    length: u64,
+}
+
+impl<R: Read + Seek> Multipart<R> {
+    pub fn new(source: R, ranges: &[ByteRange], total: u64, content_type: Option<&[u8]>,
+               boundary: &str)
+        -> Self
+    {
+        let mut parts = VecDeque::new();
+        let mut length = 0;
+        for range in ranges {
+            let mut head = format!("--{}\r\n", boundary).into_bytes();
+            if let Some(content_type) = content_type {
+                head.extend(b"Content-Type: ");
+                head.extend(content_type);
+                head.extend(b"\r\n");
+            }
+            head.extend(format!("Content-Range: {}\r\n\r\n", range.content_range(total))
+                .as_bytes());
+            length += head.len() as u64 + range.len() + 2;
+            parts.push_back(Part::Data(Cursor::new(head)));
+            parts.push_back(Part::Range(*range));
+            parts.push_back(Part::Data(Cursor::new(b"\r\n".to_vec())));
+        }
+        let tail = format!("--{}--\r\n", boundary).into_bytes();
+        length += tail.len() as u64;
+        parts.push_back(Part::Data(Cursor::new(tail)));
+
+        Multipart {
+            source: source,
+            parts: parts,
+            length: length,
+        }
+    }
+
+    pub fn len(&self) -> u64 {
+        self.length
+    }
+}
+
+impl<R: Read + Seek> Read for Multipart<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        loop {
+            let size = match self.parts.front_mut() {
+                None => return Ok(0),
+                Some(&mut Part::Data(ref mut data)) => try!(data.read(buf)),
+                Some(&mut Part::Range(ref mut range)) => {
+                    let size = cmp::min(range.len(), buf.len() as u64) as usize;
+                    try!(self.source.seek(SeekFrom::Start(range.start)));
+                    let size = try!(self.source.read(&mut buf[..size]));
+                    if size == 0 {
+                        return Err(io::Error::new(io::ErrorKind::UnexpectedEof,
+                                                  "Content is shorter than the range"));
+                    }
+                    range.start += size as u64;
+                    size
+                }
+            };
+
+            let finished = match self.parts.front() {
+                Some(&Part::Data(ref data)) => data.position() == data.get_ref().len() as u64,
+                Some(&Part::Range(ref range)) => range.start > range.end,
+                None => false,
+            };
+            if finished {
+                self.parts.pop_front();
+            }
+            if size > 0 {
+                return Ok(size);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{Cursor, Read};
+
+    use super::{parse, ByteRange, Multipart, RangeError};
+
+    #[test]
+    fn parse_ranges() {
+        assert_eq!(parse("bytes=0-9", 100), Ok(vec![ByteRange { start: 0, end: 9 }]));
+        assert_eq!(parse("bytes=90-", 100), Ok(vec![ByteRange { start: 90, end: 99 }]));
+        assert_eq!(parse("bytes=-10", 100), Ok(vec![ByteRange { start: 90, end: 99 }]));
+        assert_eq!(parse("bytes=95-200", 100), Ok(vec![ByteRange { start: 95, end: 99 }]));
+        assert_eq!(parse("bytes=0-0, -1", 100),
+                   Ok(vec![ByteRange { start: 0, end: 0 }, ByteRange { start: 99, end: 99 }]));
+    }
+
+    #[test]
+    fn merge_ranges() {
+        assert_eq!(parse("bytes=50-59, 0-9, 5-20, 21-30", 100),
+                   Ok(vec![ByteRange { start: 0, end: 30 }, ByteRange { start: 50, end: 59 }]));
+        assert_eq!(parse("bytes=0-, 0-, 0-", 100), Ok(vec![ByteRange { start: 0, end: 99 }]));
+        let many = (0..20).map(|i| format!("{}-{}", i * 2, i * 2)).collect::<Vec<_>>().join(",");
+        assert_eq!(parse(&format!("bytes={}", many), 100), Err(RangeError::TooMany));
+    }
+
+    #[test]
+    fn multipart_body() {
+        let source = Cursor::new(b"0123456789".to_vec());
+        let ranges = [ByteRange { start: 1, end: 2 }, ByteRange { start: 8, end: 9 }];
+        let mut multipart = Multipart::new(source, &ranges, 10, Some(b"text/plain"), "b");
+        let length = multipart.len();
+        let mut body = String::new();
+        multipart.read_to_string(&mut body).unwrap();
+        assert_eq!(body, "--b\r\nContent-Type: text/plain\r\nContent-Range: bytes 1-2/10\r\n\r\n\
+                          12\r\n\
+                          --b\r\nContent-Type: text/plain\r\nContent-Range: bytes 8-9/10\r\n\r\n\
+                          89\r\n\
+                          --b--\r\n");
+        assert_eq!(body.len() as u64, length);
+    }
+
+    #[test]
+    fn parse_invalid_ranges() {
+        assert_eq!(parse("items=0-9", 100), Err(RangeError::Invalid));
+        assert_eq!(parse("bytes=9-0", 100), Err(RangeError::Invalid));
+        assert_eq!(parse("bytes=a-b", 100), Err(RangeError::Invalid));
+        assert_eq!(parse("bytes=", 100), Err(RangeError::Invalid));
+        assert_eq!(parse("bytes=100-", 100), Err(RangeError::Unsatisfiable));
+        assert_eq!(parse("bytes=-0", 100), Err(RangeError::Unsatisfiable));
+    }
+}
@@ -0,0 +1,246 @@
+use std::collections::BTreeMap;
+use std::fs::{self, File};
+use std::io::Write;
+use std::iter;
+use std::path::{Path, PathBuf};
+use std::str;
+
+use regex;
+
+use super::request::Headers;
+
+pub const CONFIG_FILE: &'static str = "responder.yaml";
+
+const INLINE_BODY_LIMIT: usize = 4096;
+
+const SKIPPED_HEADERS: &'static [&'static str] = &[
+    "Connection", "Content-Length", "Content-Type", "Date", "ETag", "Keep-Alive",
+    "Transfer-Encoding", "Upgrade",
+];
+
+#[derive(Debug)]
+enum Content {
+    Data(String),
+    File(PathBuf),
+}
+
+#[derive(Debug)]
+struct Recorded {
+    status: u16,
+    content_type: Option<String>,
+    encoded: bool,
+    headers: Vec<(String, String)>,
+    content: Option<Content>,
+}
+
+#[derive(Debug)]
+pub struct Recorder {
+    dir: PathBuf,
+    routes: BTreeMap<String, BTreeMap<String, Recorded>>,
+    bodies: usize,
+}
+
+impl Recorder {
+    pub fn new(dir: PathBuf) -> Self {
+        Recorder {
+            dir: dir,
+            routes: BTreeMap::new(),
+            bodies: 0,
+        }
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    pub fn record(&mut self, method: &str, path: &str, status: u16, headers: &Headers, body: &[u8])
+        -> Result<bool, String>
+    {
+        if self.routes.get(path).map_or(false, |methods| methods.contains_key(method)) {
+            return Ok(false);
+        }
+
+        let header = |name: &str| {
+            headers.iter()
+                .find(|&&(ref k, _)| k.eq_ignore_ascii_case(name))
+                .map(|&(_, ref v)| String::from_utf8_lossy(v).into_owned())
+        };
+
+        let content = if body.is_empty() {
+            None
+        } else {
+            match str::from_utf8(body).ok().and_then(quote) {
+                Some(text) if body.len() <= INLINE_BODY_LIMIT => Some(Content::Data(text)),
+                _ => Some(Content::File(try!(self.write_body(body, header("Content-Type"))))),
+            }
+        };
+
+        // headers are written as a mapping, so only the first one of each name is kept
+        let mut recorded_headers: Vec<(String, String)> = Vec::new();
+        for &(ref name, ref value) in headers.iter() {
+            let skipped = SKIPPED_HEADERS.iter().any(|h| h.eq_ignore_ascii_case(name));
+            let repeated = recorded_headers.iter().any(|&(ref k, _)| k.eq_ignore_ascii_case(name));
+            if !skipped && !repeated {
+                recorded_headers.push((name.clone(), String::from_utf8_lossy(value).into_owned()));
+            }
+        }
+
+        let recorded = Recorded {
+            status: status,
+            content_type: header("Content-Type"),
+            encoded: header("Content-Encoding").is_some(),
+            headers: recorded_headers,
+            content: content,
+        };
+
+        self.routes.entry(path.to_owned())
+            .or_insert_with(BTreeMap::new)
+            .insert(method.to_owned(), recorded);
+        Ok(true)
+    }
+
+    fn write_body(&mut self, body: &[u8], content_type: Option<String>) -> Result<PathBuf, String> {
+        self.bodies += 1;
+        // `application/problem+json` is saved as `.json`
+        let extension = content_type.as_ref()
+            .and_then(|t| t.split(';').next())
+            .and_then(|t| t.trim().split('/').nth(1))
+            .and_then(|t| t.split('+').last())
+            .map(|t| t.to_ascii_lowercase())
+            .and_then(|t| if !t.is_empty() && t.chars().all(is_name_char) { Some(t) } else { None })
+            .unwrap_or("bin".to_owned());
+        let path = self.dir.join("bodies").join(format!("{:04}.{}", self.bodies, extension));
+
+        try!(path.parent().map_or(Ok(()), |dir| fs::create_dir_all(dir))
+            .and_then(|_| File::create(&path))
+            .and_then(|mut file| file.write_all(body))
+            .map_err(|e| format!("Error writing {}: {}", path.display(), e)));
+        Ok(path)
+    }
+
+    pub fn write(&self) -> Result<(), String> {
+        let mut main = String::from("routes:\n");
+        let mut includes: BTreeMap<&str, String> = BTreeMap::new();
+
+        for (path, methods) in self.routes.iter() {
+            match split_prefix(path) {
+                Some((prefix, rest)) => {
+                    let include = includes.entry(prefix).or_insert_with(String::new);
+                    write_route(include, 0, rest, methods);
+                }
+                None => write_route(&mut main, 2, path, methods),
+            }
+        }
+
+        for (prefix, routes) in includes.iter() {
+            let name = include_name(prefix);
+            main.push_str(&format!("  {}: !Include {}\n",
+                                   quote_lossy(&regex::quote(prefix)),
+                                   quote_lossy(&self.dir.join(&name).to_string_lossy())));
+            try!(write_file(&self.dir.join(&name), routes));
+        }
+
+        write_file(&self.dir.join(CONFIG_FILE), &main)
+    }
+}
+
+fn write_file(path: &Path, data: &str) -> Result<(), String> {
+    path.parent().map_or(Ok(()), |dir| fs::create_dir_all(dir))
+        .and_then(|_| File::create(path))
+        .and_then(|mut file| file.write_all(data.as_bytes()))
+        .map_err(|e| format!("Error writing {}: {}", path.display(), e))
+}
+
+fn write_route(out: &mut String, indent: usize, path: &str, methods: &BTreeMap<String, Recorded>) {
+    let pad: String = iter::repeat(' ').take(indent).collect();
+    // the trailing `$` keeps the route apart from an include with the same prefix
+    let route = format!("{}$", regex::quote(path));
+    out.push_str(&format!("{}{}: !Handler\n", pad, quote_lossy(&route)));
+    for (method, recorded) in methods.iter() {
+        out.push_str(&format!("{}  {}:\n", pad, method));
+        out.push_str(&format!("{}    status: {}\n", pad, recorded.status));
+        if let Some(ref content_type) = recorded.content_type {
+            out.push_str(&format!("{}    content-type: {}\n", pad, quote_lossy(content_type)));
+        }
+        if recorded.encoded {
+            out.push_str(&format!("{}    compress: false\n", pad));
+        }
+        if !recorded.headers.is_empty() {
+            out.push_str(&format!("{}    headers:\n", pad));
+            for &(ref name, ref value) in recorded.headers.iter() {
+                out.push_str(&format!("{}      {}: {}\n", pad, quote_lossy(name), quote_lossy(value)));
+            }
+        }
+        match recorded.content {
+            Some(Content::Data(ref data)) => {
+                out.push_str(&format!("{}    content: !Data {}\n", pad, data));
+            }
+            Some(Content::File(ref path)) => {
+                out.push_str(&format!("{}    content: !File {}\n", pad,
+                                      quote_lossy(&path.to_string_lossy())));
+            }
+            None => {}
+        }
+    }
+}
+
+fn split_prefix(path: &str) -> Option<(&str, &str)> {
+    if !path.starts_with('/') {
+        return None;
+    }
+    let end = path.find('?').unwrap_or(path.len());
+    path[1..end].find('/').map(|i| (&path[..i + 1], &path[i + 1..]))
+}
+
+fn include_name(prefix: &str) -> String {
+    let name: String = prefix.trim_left_matches('/').chars()
+        .map(|c| if is_name_char(c) || c == '-' || c == '_' { c } else { '_' })
+        .collect();
+    format!("{}.yaml", name)
+}
+
+fn is_name_char(c: char) -> bool {
+    c.is_ascii() && c.is_alphanumeric()
+}
+
+pub fn quote(text: &str) -> Option<String> {
+    let mut quoted = String::with_capacity(text.len() + 2);
+    quoted.push('"');
+    for c in text.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '\n' => quoted.push_str("\\n"),
+            '\r' => quoted.push_str("\\r"),
+            '\t' => quoted.push_str("\\t"),
+            c if c.is_control() => return None,
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    Some(quoted)
+}
+
+pub fn quote_lossy(text: &str) -> String {
+    let text: String = text.chars().filter(|&c| !c.is_control() || "\n\r\t".contains(c)).collect();
+    quote(&text).unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{quote, split_prefix};
+
+    #[test]
+    fn quote_yaml_string() {
+        assert_eq!(quote("say \"hi\"\n\\o/").unwrap(), r#""say \"hi\"\n\\o/""#);
+        assert_eq!(quote("bell\x07"), None);
+    }
+
+    #[test]
+    fn split_path_prefix() {
+        assert_eq!(split_prefix("/api/users/1?x=/y"), Some(("/api", "/users/1?x=/y")));
+        assert_eq!(split_prefix("/api/"), Some(("/api", "/")));
+        assert_eq!(split_prefix("/api?x=/y"), None);
+        assert_eq!(split_prefix("/"), None);
+    }
+}
@@ -0,0 +1,43 @@
+use std::str;
+
+use rotor_http::server::Head;
+
+pub type Headers = Vec<(String, Vec<u8>)>;
+
+#[derive(Clone, Debug)]
+pub struct Request {
+    pub method: String,
+    pub path: String,
+    pub headers: Headers,
+}
+
+impl Request {
+    pub fn from_head(head: &Head) -> Self {
+        Request {
+            method: head.method.to_owned(),
+            path: head.path.to_owned(),
+            headers: head.headers.iter()
+                .map(|h| (h.name.to_owned(), h.value.to_owned()))
+                .collect(),
+        }
+    }
+
+    pub fn header(&self, name: &str) -> Option<&[u8]> {
+        self.headers.iter()
+            .find(|&&(ref k, _)| k.eq_ignore_ascii_case(name))
+            .map(|&(_, ref v)| &v[..])
+    }
+
+    pub fn header_str(&self, name: &str) -> Option<&str> {
+        self.header(name).and_then(|v| str::from_utf8(v).ok()).map(|v| v.trim())
+    }
+}
+
+#[cfg(test)]
+pub fn with_header(method: &str, name: &str, value: &str) -> Request {
+    Request {
+        method: method.to_owned(),
+        path: "/".to_owned(),
+        headers: vec![(name.to_owned(), value.as_bytes().to_owned())],
+    }
+}
@@ -0,0 +1,329 @@
+use std::collections::BTreeSet;
+
+use regex::Regex;
+use rustc_serialize::json::Json;
+
+use super::openapi;
+
+pub const MAX_DEPTH: usize = 64;
+
+const TOO_DEEP: &'static str = "nested too deeply";
+
+pub fn validate(spec: &Json, schema: &Json, value: &Json, location: &str, errors: &mut Vec<String>) {
+    check(spec, schema, value, location, 0, errors)
+}
+
+fn check(spec: &Json, schema: &Json, value: &Json, location: &str, depth: usize,
+         errors: &mut Vec<String>)
+{
+    if depth > MAX_DEPTH {
+        errors.push(format!("{}: {}", location, TOO_DEEP));
+        return;
+    }
+
+    let schema = match openapi::resolve(spec, schema) {
+        Ok(schema) => schema,
+        Err(e) => {
+            errors.push(format!("{}: {}", location, e));
+            return;
+        }
+    };
+
+    if value.is_null() && schema.find("nullable").and_then(|n| n.as_boolean()) == Some(true) {
+        return;
+    }
+
+    let types: Vec<&str> = match schema.find("type") {
+        Some(&Json::String(ref name)) => vec![&name[..]],
+        Some(&Json::Array(ref names)) => names.iter().filter_map(|n| n.as_string()).collect(),
+        _ => Vec::new(),
+    };
+    if !types.is_empty() && !types.iter().any(|t| is_type(value, t)) {
+        errors.push(format!("{}: expected {}, found {}", location, types.join(" or "),
+                            type_name(value)));
+        return;
+    }
+
+    if let Some(values) = schema.find("enum").and_then(|e| e.as_array()) {
+        if !values.iter().any(|v| equals(v, value)) {
+            errors.push(format!("{}: {} is not one of the allowed values", location, value));
+        }
+    }
+
+    if let Err(e) = check_combinations(spec, schema, value, location, depth, errors) {
+        errors.push(e);
+        return;
+    }
+
+    match *value {
+        Json::String(ref text) => check_string(schema, text, location, errors),
+        Json::I64(_) | Json::U64(_) | Json::F64(_) => {
+            check_number(schema, value.as_f64().unwrap_or(0.0), location, errors);
+        }
+        Json::Array(ref items) => check_array(spec, schema, items, location, depth, errors),
+        Json::Object(ref object) => {
+            let required = schema.find("required").and_then(|r| r.as_array());
+            for name in required.iter().flat_map(|r| r.iter()).filter_map(|n| n.as_string()) {
+                // read only properties are only required in responses
+                let read_only = schema.find_path(&["properties", name])
+                    .and_then(|p| openapi::resolve(spec, p).ok())
+                    .and_then(|p| p.find("readOnly"))
+                    .and_then(|r| r.as_boolean())
+                    .unwrap_or(false);
+                if !object.contains_key(name) && !read_only {
+                    errors.push(format!("{}: missing required property {}", location, name));
+                }
+            }
+
+            let properties = schema.find("properties").and_then(|p| p.as_object());
+            for (name, property) in object.iter() {
+                let location = format!("{}.{}", location, name);
+                match (properties.and_then(|p| p.get(name)), schema.find("additionalProperties")) {
+                    (Some(property_schema), _) => {
+                        check(spec, property_schema, property, &location, depth + 1, errors);
+                    }
+                    (None, Some(&Json::Boolean(false))) => {
+                        errors.push(format!("{}: unknown property", location));
+                    }
+                    (None, Some(additional)) if additional.is_object() => {
+                        check(spec, additional, property, &location, depth + 1, errors);
+                    }
+                    _ => {}
+                }
+            }
+
+            let count = object.len() as u64;
+            if let Some(min) = schema.find("minProperties").and_then(|m| m.as_u64()) {
+                if count < min {
+                    errors.push(format!("{}: expected at least {} properties", location, min));
+                }
+            }
+            if let Some(max) = schema.find("maxProperties").and_then(|m| m.as_u64()) {
+                if count > max {
+                    errors.push(format!("{}: expected at most {} properties", location, max));
+                }
+            }
+        }
+        Json::Boolean(_) | Json::Null => {}
+    }
+}
+
+/// Fails as soon as a schema is nested too deeply instead of trying the others
+fn check_combinations(spec: &Json, schema: &Json, value: &Json, location: &str, depth: usize,
+                      errors: &mut Vec<String>)
+                      -> Result<(), String>
+{
+    let matches = |schema: &Json| {
+        let mut errors = Vec::new();
+        check(spec, schema, value, location, depth + 1, &mut errors);
+        match errors.iter().find(|e| e.ends_with(TOO_DEEP)) {
+            Some(e) => Err(e.clone()),
+            None => Ok(errors.is_empty()),
+        }
+    };
+
+    if let Some(schemas) = schema.find("allOf").and_then(|s| s.as_array()) {
+        for schema in schemas.iter() {
+            check(spec, schema, value, location, depth + 1, errors);
+        }
+    }
+    if let Some(schemas) = schema.find("anyOf").and_then(|s| s.as_array()) {
+        let mut matched = false;
+        for schema in schemas.iter() {
+            if try!(matches(schema)) {
+                matched = true;
+                break;
+            }
+        }
+        if !matched {
+            errors.push(format!("{}: does not match any of the allowed schemas", location));
+        }
+    }
+    if let Some(schemas) = schema.find("oneOf").and_then(|s| s.as_array()) {
+        let mut count = 0;
+        for schema in schemas.iter() {
+            if try!(matches(schema)) {
+                count += 1;
+            }
+        }
+        if count != 1 {
+            errors.push(format!("{}: matches {} schemas instead of exactly one", location, count));
+        }
+    }
+    if let Some(schema) = schema.find("not") {
+        if try!(matches(schema)) {
+            errors.push(format!("{}: matches a schema that is not allowed", location));
+        }
+    }
+    Ok(())
+}
+
+fn check_string(schema: &Json, text: &str, location: &str, errors: &mut Vec<String>) {
+    let length = text.chars().count() as u64;
+    if let Some(min) = schema.find("minLength").and_then(|m| m.as_u64()) {
+        if length < min {
+            errors.push(format!("{}: expected at least {} characters", location, min));
+        }
+    }
+    if let Some(max) = schema.find("maxLength").and_then(|m| m.as_u64()) {
+        if length > max {
+            errors.push(format!("{}: expected at most {} characters", location, max));
+        }
+    }
+    // patterns that are not supported by the regex crate are ignored
+    if let Some(pattern) = schema.find("pattern").and_then(|p| p.as_string()) {
+        if Regex::new(pattern).map(|re| !re.is_match(text)).unwrap_or(false) {
+            errors.push(format!("{}: does not match the pattern {}", location, pattern));
+        }
+    }
+}
+
+fn check_number(schema: &Json, number: f64, location: &str, errors: &mut Vec<String>) {
+    // OpenAPI 3.0 uses booleans for the exclusive bounds, JSON Schema uses numbers
+    let exclusive = |name: &str| schema.find(name).and_then(|e| e.as_boolean()).unwrap_or(false);
+
+    let minimum = schema.find("minimum").and_then(|m| m.as_f64());
+    let exclusive_minimum = schema.find("exclusiveMinimum").and_then(|m| m.as_f64());
+    match (minimum, exclusive_minimum) {
+        (Some(min), _) if exclusive("exclusiveMinimum") && number <= min => {
+            errors.push(format!("{}: expected a value greater than {}", location, min));
+        }
+        (Some(min), _) if number < min => {
+            errors.push(format!("{}: expected a value of at least {}", location, min));
+        }
+        (_, Some(min)) if number <= min => {
+            errors.push(format!("{}: expected a value greater than {}", location, min));
+        }
+        _ => {}
+    }
+
+    let maximum = schema.find("maximum").and_then(|m| m.as_f64());
+    let exclusive_maximum = schema.find("exclusiveMaximum").and_then(|m| m.as_f64());
+    match (maximum, exclusive_maximum) {
+        (Some(max), _) if exclusive("exclusiveMaximum") && number >= max => {
+            errors.push(format!("{}: expected a value less than {}", location, max));
+        }
+        (Some(max), _) if number > max => {
+            errors.push(format!("{}: expected a value of at most {}", location, max));
+        }
+        (_, Some(max)) if number >= max => {
+            errors.push(format!("{}: expected a value less than {}", location, max));
+        }
+        _ => {}
+    }
+
+    if let Some(divisor) = schema.find("multipleOf").and_then(|m| m.as_f64()) {
+        if divisor > 0.0 && (number / divisor).fract() != 0.0 {
+            errors.push(format!("{}: expected a multiple of {}", location, divisor));
+        }
+    }
+}
+
+fn check_array(spec: &Json, schema: &Json, items: &[Json], location: &str, depth: usize,
+               errors: &mut Vec<String>)
+{
+    let count = items.len() as u64;
+    if let Some(min) = schema.find("minItems").and_then(|m| m.as_u64()) {
+        if count < min {
+            errors.push(format!("{}: expected at least {} items", location, min));
+        }
+    }
+    if let Some(max) = schema.find("maxItems").and_then(|m| m.as_u64()) {
+        if count > max {
+            errors.push(format!("{}: expected at most {} items", location, max));
+        }
+    }
+    if schema.find("uniqueItems").and_then(|u| u.as_boolean()) == Some(true) {
+        let unique: BTreeSet<String> = items.iter().map(|i| i.to_string()).collect();
+        if unique.len() != items.len() {
+            errors.push(format!("{}: expected unique items", location));
+        }
+    }
+    if let Some(item_schema) = schema.find("items") {
+        for (i, item) in items.iter().enumerate() {
+            check(spec, item_schema, item, &format!("{}[{}]", location, i), depth + 1, errors);
+        }
+    }
+}
+
+fn is_type(value: &Json, name: &str) -> bool {
+    match (name, value) {
+        ("integer", &Json::F64(f)) => f.fract() == 0.0,
+        ("integer", _) => value.is_i64() || value.is_u64(),
+        ("number", _) => value.is_number(),
+        ("string", _) => value.is_string(),
+        ("boolean", _) => value.is_boolean(),
+        ("array", _) => value.is_array(),
+        ("object", _) => value.is_object(),
+        ("null", _) => value.is_null(),
+        _ => true,
+    }
+}
+
+fn type_name(value: &Json) -> &'static str {
+    match *value {
+        Json::I64(_) | Json::U64(_) => "integer",
+        Json::F64(_) => "number",
+        Json::String(_) => "string",
+        Json::Boolean(_) => "boolean",
+        Json::Array(_) => "array",
+        Json::Object(_) => "object",
+        Json::Null => "null",
+    }
+}
+
+fn equals(a: &Json, b: &Json) -> bool {
+    match (a.as_f64(), b.as_f64()) {
+        (Some(a), Some(b)) => a == b,
+        _ => a == b,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rustc_serialize::json::Json;
+    use super::validate;
+
+    #[test]
+    fn validate_object() {
+        let spec = Json::from_str(r##"{"components": {"schemas": {"Pet": {
+            "type": "object",
+            "required": ["id", "name"],
+            "properties": {
+                "id": {"type": "integer", "readOnly": true},
+                "name": {"type": "string", "minLength": 1},
+                "tags": {"type": "array", "items": {"enum": ["cat", "dog"]}}}}}}}"##).unwrap();
+        let schema = Json::from_str(r##"{"$ref": "#/components/schemas/Pet"}"##).unwrap();
+
+        let mut errors = Vec::new();
+        validate(&spec, &schema, &Json::from_str(r#"{"name": "Rex"}"#).unwrap(), "body",
+                 &mut errors);
+        assert!(errors.is_empty());
+
+        let pet = Json::from_str(r#"{"name": 7, "tags": ["dog", "fish"]}"#).unwrap();
+        validate(&spec, &schema, &pet, "body", &mut errors);
+        assert_eq!(errors, vec!["body.name: expected string, found integer".to_owned(),
+                                "body.tags[1]: \"fish\" is not one of the allowed values".to_owned()]);
+    }
+
+    #[test]
+    fn validate_recursive_schema() {
+        let spec = Json::from_str(r##"{"components": {"schemas": {
+            "Loop": {"anyOf": [{"$ref": "#/components/schemas/Loop"},
+                               {"$ref": "#/components/schemas/Loop"}]},
+            "Tree": {"type": "array", "items": {"$ref": "#/components/schemas/Tree"}}}}}"##)
+            .unwrap();
+
+        let mut errors = Vec::new();
+        let schema = Json::from_str(r##"{"$ref": "#/components/schemas/Loop"}"##).unwrap();
+        validate(&spec, &schema, &Json::Null, "body", &mut errors);
+        assert_eq!(errors, vec!["body: nested too deeply".to_owned()]);
+
+        let mut errors = Vec::new();
+        let schema = Json::from_str(r##"{"$ref": "#/components/schemas/Tree"}"##).unwrap();
+        let value = (0..100).fold(Json::Array(Vec::new()), |v, _| Json::Array(vec![v]));
+        validate(&spec, &schema, &value, "body", &mut errors);
+        assert_eq!(errors.len(), 1);
+        assert!(errors[0].ends_with("nested too deeply"));
+    }
+}
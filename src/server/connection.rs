@@ -0,0 +1,300 @@
+use std::io;
+use std::mem;
+use std::time::Duration;
+
+use httparse;
+#[cfg(feature = "tls")]
+use openssl::ssl::{HandshakeError, MidHandshakeSslStream, SslStream};
+use rotor::{EventSet, Machine, PollOpt, Response, Scope, Time};
+#[cfg(feature = "tls")]
+use rotor::mio::tcp::TcpStream;
+use rotor::void::{self, Void};
+use rotor_http::server::{Parser, Stream};
+use rotor_stream::{Accepted, Buf};
+
+use context::Context;
+use transfer;
+use websocket::{self, Session};
+use super::engine::{host, SharedOutput, Responder, Router};
+use super::socket::Socket;
+use super::Tls;
+
+const SNIFF_TIMEOUT: u64 = 10;
+
+const RAW_TIMEOUT: u64 = 10;
+
+/// Peeks the request head to choose between a websocket `Session` and the HTTP parser
+pub enum Connection {
+    #[cfg(feature = "tls")]
+    Handshake(MidHandshakeSslStream<TcpStream>, usize, Time),
+    Pending(Socket, usize, Time),
+    Http(Stream<Parser<Responder, Socket>>, SharedOutput),
+    Raw(Stream<Parser<Responder, Socket>>, Time),
+    WebSocket(Stream<Session>),
+}
+
+impl Connection {
+    #[cfg(feature = "tls")]
+    fn handshake(stream: MidHandshakeSslStream<TcpStream>, listener: usize, deadline: Time)
+        -> Response<Self, Void>
+    {
+        Response::ok(Connection::Handshake(stream, listener, deadline)).deadline(deadline)
+    }
+
+    fn pending(sock: Socket, listener: usize, deadline: Time) -> Response<Self, Void> {
+        Response::ok(Connection::Pending(sock, listener, deadline)).deadline(deadline)
+    }
+
+    fn http(res: Response<Stream<Parser<Responder, Socket>>, Void>, output: SharedOutput,
+            scope: &mut Scope<Context>)
+        -> Response<Self, Void>
+    {
+        if output.lock().unwrap().reset && !res.is_stopped() {
+            let mut stream = res.expect_machine();
+            if let Err(e) = stream.transport().socket().reset() {
+                debug!("Cannot reset connection: {}", e);
+            }
+            return Response::done();
+        }
+        if output.lock().unwrap().raw.is_empty() || res.is_stopped() {
+            return res.wrap(|mut m| {
+                {
+                    let mut out = output.lock().unwrap();
+                    out.buffered = m.transport().output().len();
+                    if out.waiting && out.buffered < transfer::BLOCK_SIZE as usize {
+                        out.waiting = false;
+                        scope.notifier().wakeup().unwrap();
+                    }
+                }
+                Connection::Http(m, output)
+            });
+        }
+        let mut stream = res.expect_machine();
+        stream.transport().output().extend(&output.lock().unwrap().raw);
+        let deadline = scope.now() + Duration::new(RAW_TIMEOUT, 0);
+        Connection::raw(stream, deadline)
+    }
+
+    fn raw(mut stream: Stream<Parser<Responder, Socket>>, deadline: Time)
+        -> Response<Self, Void>
+    {
+        let result = {
+            let mut transport = stream.transport();
+            let mut output = mem::replace(transport.output(), Buf::new());
+            let mut result = Ok(());
+            while !output.is_empty() {
+                result = output.write_to(transport.socket()).map(|_| ());
+                if result.is_err() {
+                    break;
+                }
+            }
+            *transport.output() = output;
+            result
+        };
+        match result {
+            Ok(()) => Response::done(),
+            Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => {
+                Response::ok(Connection::Raw(stream, deadline)).deadline(deadline)
+            }
+            Err(_) => Response::done(),
+        }
+    }
+}
+
+#[cfg(feature = "tls")]
+fn continue_handshake(result: Result<SslStream<TcpStream>, HandshakeError<TcpStream>>,
+                      listener: usize, deadline: Time, scope: &mut Scope<Context>)
+    -> Response<Connection, Void>
+{
+    match result {
+        // the request head may already be decrypted, so it is not
+        // announced by the socket
+        Ok(stream) => select_protocol(Socket::Tls(stream), listener, deadline, scope),
+        Err(HandshakeError::WouldBlock(stream)) => {
+            Connection::handshake(stream, listener, deadline)
+        }
+        Err(HandshakeError::Failure(stream)) => {
+            debug!("TLS handshake failed: {}", stream.error());
+            Response::done()
+        }
+        Err(HandshakeError::SetupFailure(e)) => {
+            error!("Cannot start TLS session: {}", e);
+            Response::done()
+        }
+    }
+}
+
+fn select_protocol(mut sock: Socket, listener: usize, deadline: Time,
+                   scope: &mut Scope<Context>)
+    -> Response<Connection, Void>
+{
+    let mut buf = [0u8; websocket::MAX_HEAD];
+    let size = match sock.peek(&mut buf) {
+        Ok(0) => return Response::done(),
+        Ok(size) => size,
+        Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => {
+            return Connection::pending(sock, listener, deadline);
+        }
+        Err(_) => return into_http(sock, listener, scope),
+    };
+
+    let upgrade = {
+        let mut headers = [httparse::EMPTY_HEADER; 64];
+        let mut req = httparse::Request::new(&mut headers);
+        match req.parse(&buf[..size]) {
+            Ok(httparse::Status::Partial) if size < buf.len() => {
+                return Connection::pending(sock, listener, deadline);
+            }
+            Ok(httparse::Status::Complete(_)) if is_upgrade(&req) => {
+                let host = host(req.headers).map(|h| h.to_owned());
+                req.path.map(|p| (p.to_owned(), host))
+            }
+            _ => None,
+        }
+    };
+
+    if let Some((path, host)) = upgrade {
+        if scope.autoreload() {
+            if let Err(e) = scope.rebuild() {
+                error!("{}", e);
+                return Response::done();
+            }
+        }
+        if let Some(websocket) = scope.match_websocket(listener, host.as_ref().map(|h| &h[..]), &path) {
+            if let Err(e) = scope.deregister(&sock) {
+                return Response::error(Box::new(e));
+            }
+            return Stream::new(sock, websocket, scope).wrap(Connection::WebSocket);
+        }
+    }
+
+    into_http(sock, listener, scope)
+}
+
+fn into_http(sock: Socket, listener: usize, scope: &mut Scope<Context>)
+    -> Response<Connection, Void>
+{
+    if let Err(e) = scope.deregister(&sock) {
+        return Response::error(Box::new(e));
+    }
+    let output = SharedOutput::default();
+    Stream::new(sock, (listener, output.clone()), scope).wrap(|m| Connection::Http(m, output))
+}
+
+fn is_upgrade(req: &httparse::Request) -> bool {
+    req.method == Some("GET") && req.headers.iter().any(|h| {
+        h.name.eq_ignore_ascii_case("Upgrade") &&
+            h.value.eq_ignore_ascii_case(b"websocket")
+    })
+}
+
+impl Accepted for Connection {
+    type Seed = (usize, Option<Tls>);
+    type Socket = Socket;
+
+    fn accepted(sock: Socket, (listener, tls): (usize, Option<Tls>),
+                scope: &mut Scope<Context>)
+        -> Response<Self, Void>
+    {
+        let deadline = scope.now() + Duration::new(SNIFF_TIMEOUT, 0);
+        match (tls, sock) {
+            // TLS is only enabled on TCP listeners
+            #[cfg(feature = "tls")]
+            (Some(acceptor), Socket::Tcp(sock)) => {
+                let events = EventSet::readable() | EventSet::writable();
+                if let Err(e) = scope.register(&sock, events, PollOpt::edge()) {
+                    return Response::error(Box::new(e));
+                }
+                continue_handshake(acceptor.accept(sock), listener, deadline, scope)
+            }
+            (_, sock) => {
+                if let Err(e) = scope.register(&sock, EventSet::readable(), PollOpt::edge()) {
+                    return Response::error(Box::new(e));
+                }
+                Connection::pending(sock, listener, deadline)
+            }
+        }
+    }
+}
+
+impl Machine for Connection {
+    type Context = Context;
+    type Seed = Void;
+
+    fn create(seed: Void, _scope: &mut Scope<Context>) -> Response<Self, Void> {
+        void::unreachable(seed)
+    }
+
+    fn ready(self, events: EventSet, scope: &mut Scope<Context>) -> Response<Self, Void> {
+        match self {
+            #[cfg(feature = "tls")]
+            Connection::Handshake(stream, listener, deadline) => {
+                continue_handshake(stream.handshake(), listener, deadline, scope)
+            }
+            Connection::Pending(sock, listener, deadline) => {
+                select_protocol(sock, listener, deadline, scope)
+            }
+            Connection::Http(m, output) => {
+                let res = m.ready(events, scope);
+                Connection::http(res, output, scope)
+            }
+            Connection::Raw(m, deadline) => Connection::raw(m, deadline),
+            Connection::WebSocket(m) => m.ready(events, scope).wrap(Connection::WebSocket),
+        }
+    }
+
+    fn spawned(self, scope: &mut Scope<Context>) -> Response<Self, Void> {
+        match self {
+            #[cfg(feature = "tls")]
+            Connection::Handshake(stream, listener, deadline) => {
+                Connection::handshake(stream, listener, deadline)
+            }
+            Connection::Pending(sock, listener, deadline) => {
+                Connection::pending(sock, listener, deadline)
+            }
+            Connection::Http(m, output) => {
+                let res = m.spawned(scope);
+                Connection::http(res, output, scope)
+            }
+            Connection::Raw(m, deadline) => {
+                Response::ok(Connection::Raw(m, deadline)).deadline(deadline)
+            }
+            Connection::WebSocket(m) => m.spawned(scope).wrap(Connection::WebSocket),
+        }
+    }
+
+    fn timeout(self, scope: &mut Scope<Context>) -> Response<Self, Void> {
+        match self {
+            #[cfg(feature = "tls")]
+            Connection::Handshake(..) => Response::done(),
+            // let the HTTP parser handle slow clients
+            Connection::Pending(sock, listener, _) => into_http(sock, listener, scope),
+            Connection::Http(m, output) => {
+                let res = m.timeout(scope);
+                Connection::http(res, output, scope)
+            }
+            Connection::Raw(..) => Response::done(),
+            Connection::WebSocket(m) => m.timeout(scope).wrap(Connection::WebSocket),
+        }
+    }
+
+    fn wakeup(self, scope: &mut Scope<Context>) -> Response<Self, Void> {
+        match self {
+            #[cfg(feature = "tls")]
+            Connection::Handshake(stream, listener, deadline) => {
+                Connection::handshake(stream, listener, deadline)
+            }
+            Connection::Pending(sock, listener, deadline) => {
+                Connection::pending(sock, listener, deadline)
+            }
+            Connection::Http(m, output) => {
+                let res = m.wakeup(scope);
+                Connection::http(res, output, scope)
+            }
+            Connection::Raw(m, deadline) => {
+                Response::ok(Connection::Raw(m, deadline)).deadline(deadline)
+            }
+            Connection::WebSocket(m) => m.wakeup(scope).wrap(Connection::WebSocket),
+        }
+    }
+}
@@ -1,20 +1,29 @@
-use std::sync::Arc;
+use std::str;
+use std::sync::{Arc, Mutex};
 use std::time::Duration;
 
+use httparse;
 use rotor::{Response as RotorResponse, Scope, Time, Void};
-use rotor::mio::tcp::TcpListener;
-use rotor_http::server::{Fsm as RotorFsm, Head, RecvMode, Server, Response};
+use rotor_http::server::{Accept, Head, RecvMode, Server, Response};
 use rotor_tools::timer::{IntervalFunc, interval_func};
 
+use config::FaultKind;
 use context::Context;
 use handler::Handler;
 use http_status;
+use proxy::{Pending, Upstream};
+use request::Request;
+use transfer::{self, Transfer};
+use websocket::WebSocket;
+use super::connection::Connection;
+use super::socket::Listener;
+use super::Tls;
 
 
-pub fn new_http(lst: TcpListener, seed: <Responder as Server>::Seed, scope: &mut Scope<Context>)
+pub fn new_http(lst: Listener, index: usize, tls: Option<Tls>, scope: &mut Scope<Context>)
 -> RotorResponse<Fsm, Void>
 {
-    RotorFsm::<Responder, _>::new(lst, seed, scope).wrap(|fsm| Fsm::Http(fsm))
+    Accept::<Connection, _>::new(lst, (index, tls), scope).wrap(|fsm| Fsm::Http(fsm))
 }
 
 pub fn new_timer<F>(scope: &mut Scope<Context>, duration: Duration, func: F)
@@ -25,31 +34,110 @@ where F: FnMut(&mut Scope<Context>) + 'static + Send
 }
 
 rotor_compose!(pub enum Fsm/Seed<Context> {
-    Http(RotorFsm<Responder, TcpListener>),
+    Http(Accept<Connection, Listener>),
     Timer(IntervalFunc<Context>),
 });
 
 pub trait Router {
-    fn match_route(&self, method: &str, path: &str) -> Option<Arc<Handler>>;
+    fn match_route(&self, listener: usize, host: Option<&str>, method: &str, path: &str)
+        -> Option<Arc<Handler>>;
+    fn match_websocket(&self, listener: usize, host: Option<&str>, path: &str)
+        -> Option<Arc<WebSocket>>;
+    fn match_proxy(&self, listener: usize, host: Option<&str>, method: &str, path: &str)
+        -> Option<Arc<Upstream>>;
 }
 
 impl Router for Context {
-    fn match_route(&self, method: &str, path: &str) -> Option<Arc<Handler>> {
-        for ref route in self.routes().iter() {
+    fn match_route(&self, listener: usize, host: Option<&str>, method: &str, path: &str)
+        -> Option<Arc<Handler>>
+    {
+        for ref route in self.routes_for(listener, host).iter() {
             if route.is_match(method, path) {
-                return Some(route.handler())
+                if let Some(handler) = route.handler(path) {
+                    return Some(handler)
+                }
             }
         }
         None
     }
+
+    fn match_websocket(&self, listener: usize, host: Option<&str>, path: &str)
+        -> Option<Arc<WebSocket>>
+    {
+        self.routes_for(listener, host).iter().filter_map(|route| route.websocket(path)).next()
+    }
+
+    fn match_proxy(&self, listener: usize, host: Option<&str>, method: &str, path: &str)
+        -> Option<Arc<Upstream>>
+    {
+        self.routes_for(listener, host).iter()
+            .filter(|route| route.is_match(method, path))
+            .filter_map(|route| route.proxy())
+            .next()
+            .or_else(|| self.proxy().cloned())
+    }
+}
+
+pub fn host<'a>(headers: &[httparse::Header<'a>]) -> Option<&'a str> {
+    headers.iter()
+        .find(|h| h.name.eq_ignore_ascii_case("Host"))
+        .and_then(|h| str::from_utf8(h.value).ok())
+        .map(|h| h.trim())
+}
+
+const REQUEST_TIMEOUT: u64 = 10;
+
+const MAX_PROXY_BODY: usize = 10 * 1024 * 1024;
+
+const MAX_VALIDATED_BODY: usize = 1024 * 1024;
+
+#[derive(Debug, Default)]
+pub struct Output {
+    /// Bytes of a broken response written as they are, bypassing the HTTP encoder
+    pub raw: Vec<u8>,
+    pub reset: bool,
+    pub buffered: usize,
+    pub waiting: bool,
 }
 
-pub type MethodPath = (String, String);
+pub type SharedOutput = Arc<Mutex<Output>>;
 
-#[derive(Clone, Debug)]
+#[derive(Debug)]
 pub enum Responder {
-    Respond(Arc<Handler>, MethodPath),
-    NotFound(MethodPath),
+    Respond(Arc<Handler>, Request, SharedOutput),
+    Fault(Arc<Handler>, FaultKind, Request, SharedOutput),
+    NotFound(Request, SharedOutput),
+    Proxy(Arc<Upstream>, Request),
+    Proxying(Pending, Request),
+    Sending(Transfer, Request, SharedOutput),
+    Done,
+    Abort,
+}
+
+impl Responder {
+    fn early_wakeup(&self, context: &Context) -> Option<Duration> {
+        match *self {
+            // close the connection as soon as the response is written
+            Responder::Fault(..) => Some(Duration::new(0, 0)),
+            Responder::Respond(ref handler, _, _) => handler.transfer_delay(),
+            Responder::NotFound(..) => context.not_found_handler().and_then(|h| h.transfer_delay()),
+            _ => None,
+        }
+    }
+
+    fn next_write(transfer: Transfer, req: Request, output: SharedOutput,
+                  scope: &mut Scope<Context>)
+        -> (Self, Time)
+    {
+        let interval = transfer.interval();
+        let deadline = if interval == Duration::new(0, 0) {
+            output.lock().unwrap().waiting = true;
+            scope.now() + Duration::new(REQUEST_TIMEOUT, 0)
+        } else {
+            scope.now() + interval
+        };
+        (Responder::Sending(transfer, req, output), deadline)
+    }
 }
 
 fn send_not_found(res: &mut Response) {
@@ -64,8 +152,11 @@ fn send_not_found(res: &mut Response) {
 }
 
 fn send_error(res: &mut Response, data: &str) {
+    send_status(res, http_status::InternalServerError, data);
+}
+
+fn send_status(res: &mut Response, status: http_status::Status, data: &str) {
     let data = data.as_bytes();
-    let status = http_status::InternalServerError;
     res.status(status.code(), status.description());
     res.add_length(data.len() as u64).unwrap();
     res.add_header("Content-Type", b"text/plain").unwrap();
@@ -75,10 +166,10 @@ fn send_error(res: &mut Response, data: &str) {
 }
 
 impl Server for Responder {
-    type Seed = ();
+    type Seed = (usize, SharedOutput);
     type Context = Context;
 
-    fn headers_received(_seed: Self::Seed, head: Head, _res: &mut Response,
+    fn headers_received((listener, output): Self::Seed, head: Head, _res: &mut Response,
         scope: &mut Scope<Self::Context>)
         -> Option<(Self, RecvMode, Time)>
     {
@@ -92,53 +183,112 @@ impl Server for Responder {
             }
         }
 
-        let mp = (head.method.to_owned(), head.path.to_owned());
-        let responder = match scope.match_route(head.method, head.path) {
-            Some(handler) => Responder::Respond(handler.clone(), mp),
-            None => Responder::NotFound(mp),
+        let req = Request::from_head(&head);
+        let host = host(head.headers);
+        let responder = match scope.match_route(listener, host, head.method, head.path) {
+            Some(handler) => match handler.fault() {
+                Some(FaultKind::Reset) => {
+                    warn!("{} {} {} [fault: Reset]", handler.status, req.method, req.path);
+                    // the connection closes the socket before anything is written
+                    output.lock().unwrap().reset = true;
+                    Responder::Abort
+                }
+                Some(kind) => Responder::Fault(handler.clone(), kind, req, output),
+                None => Responder::Respond(handler.clone(), req, output),
+            },
+            None => match scope.match_proxy(listener, host, head.method, head.path) {
+                Some(upstream) => Responder::Proxy(upstream, req),
+                None => Responder::NotFound(req, output),
+            },
         };
 
-        Some((responder, RecvMode::Buffered(1024), scope.now() + Duration::new(10, 0)))
+        let mode = match responder {
+            Responder::Proxy(..) => RecvMode::Buffered(MAX_PROXY_BODY),
+            Responder::Respond(ref handler, _, _) if handler.validation().is_some() => {
+                RecvMode::Buffered(MAX_VALIDATED_BODY)
+            }
+            _ => RecvMode::Buffered(1024),
+        };
+        let delay = responder.early_wakeup(scope).unwrap_or(Duration::new(REQUEST_TIMEOUT, 0));
+        Some((responder, mode, scope.now() + delay))
     }
 
-    fn request_received(self, _data: &[u8], res: &mut Response,
+    fn request_received(self, data: &[u8], res: &mut Response,
         scope: &mut Scope<Context>)
         -> Option<Self>
     {
-        let method_path: MethodPath;
+        let request: Request;
         let status: u16;
+        let output: SharedOutput;
         let result = match self {
-            Responder::Respond(handler, mp) => {
-                method_path = mp;
+            Responder::Respond(handler, req, out) => {
+                let invalid = handler.validation()
+                    .and_then(|v| v.check(&req, data).err().map(|errors| (v, errors)));
+                let handler = match invalid {
+                    Some((validation, errors)) => {
+                        warn!("Invalid request {} {}: {}", req.method, req.path, errors.join("; "));
+                        Arc::new(validation.invalid(&errors))
+                    }
+                    None => handler,
+                };
                 status = handler.status;
-                handler.handle(res)
+                let result = handler.handle(&req, res);
+                request = req;
+                output = out;
+                result
             },
-            Responder::NotFound(mp) => {
-                method_path = mp;
+            Responder::Fault(handler, kind, req, out) => {
+                return match handler.inject_fault(&kind, res) {
+                    Ok(data) => {
+                        warn!("{} {} {} [fault: {:?}]", handler.status, req.method, req.path, kind);
+                        if let Some(data) = data {
+                            out.lock().unwrap().raw.extend(data);
+                        }
+                        Some(Responder::Abort)
+                    }
+                    Err(e) => {
+                        error!("500 {} {}", req.method, req.path);
+                        error!("{}", &e);
+                        send_error(res, &e);
+                        None
+                    }
+                };
+            }
+            Responder::NotFound(req, out) => {
                 status = 404;
-                match scope.not_found_handler() {
-                    Some(ref handler) => handler.handle(res),
-                    None => { send_not_found(res); Ok(()) },
-                }
+                let result = match scope.not_found_handler() {
+                    Some(ref handler) => handler.handle(&req, res),
+                    None => { send_not_found(res); Ok(None) },
+                };
+                request = req;
+                output = out;
+                result
+            }
+            Responder::Proxy(upstream, req) => {
+                let pending = upstream.forward(req.clone(), data.to_owned(), scope.notifier());
+                return Some(Responder::Proxying(pending, req));
+            }
+            Responder::Abort => return Some(Responder::Abort),
+            Responder::Proxying(..) | Responder::Sending(..) | Responder::Done => {
+                unreachable!()
             }
         };
 
         result
-        .map(|_| {
+        .map(|transfer| {
             if status == 404 {
-                warn!("{} {} {}", status, method_path.0, method_path.1);
+                warn!("{} {} {}", status, request.method, request.path);
             } else {
-                info!("{} {} {}", status, method_path.0, method_path.1);
+                info!("{} {} {}", status, request.method, request.path);
             }
+            transfer.map(|t| Responder::Sending(t, request.clone(), output))
         })
         .map_err(|e| {
-            error!("500 {} {}", method_path.0, method_path.1);
+            error!("500 {} {}", request.method, request.path);
             error!("{}", &e);
             send_error(res, &e)
         })
-        .ok();
-
-        None
+        .unwrap_or(None)
     }
 
     fn request_chunk(self, _chunk: &[u8], _response: &mut Response,
@@ -155,15 +305,89 @@ impl Server for Responder {
         unreachable!();
     }
 
-    fn timeout(self, _response: &mut Response, _scope: &mut Scope<Context>)
+    fn timeout(self, response: &mut Response, scope: &mut Scope<Context>)
         -> Option<(Self, Time)>
     {
-        unimplemented!();
+        match self {
+            Responder::Sending(mut transfer, req, output) => {
+                // the next block waits until the previous one is sent
+                if output.lock().unwrap().buffered >= transfer::BLOCK_SIZE as usize {
+                    return Some(Responder::next_write(transfer, req, output, scope));
+                }
+                match transfer.write_next(response) {
+                    Ok(false) => Some(Responder::next_write(transfer, req, output, scope)),
+                    Ok(true) => {
+                        // the response can only be finished from a wakeup,
+                        // returning None here would drop the connection
+                        scope.notifier().wakeup().unwrap();
+                        Some((Responder::Done, scope.now() + Duration::new(REQUEST_TIMEOUT, 0)))
+                    }
+                    Err(e) => {
+                        error!("Error sending {} {}: {}", req.method, req.path, e);
+                        None
+                    }
+                }
+            }
+            // the upstream server has its own timeout
+            Responder::Proxying(..) => {
+                Some((self, scope.now() + Duration::new(REQUEST_TIMEOUT, 0)))
+            }
+            Responder::Abort => {
+                if !response.is_started() {
+                    let status = http_status::OK;
+                    response.status(status.code(), status.description());
+                }
+                None
+            }
+            // request body is not fully received yet
+            _ if self.early_wakeup(scope).is_some() => {
+                Some((self, scope.now() + Duration::new(REQUEST_TIMEOUT, 0)))
+            }
+            _ => None,
+        }
     }
 
-    fn wakeup(self, _response: &mut Response, _scope: &mut Scope<Context>)
+    fn wakeup(self, response: &mut Response, scope: &mut Scope<Context>)
         -> Option<Self>
     {
-        unimplemented!();
+        match self {
+            Responder::Proxying(pending, req) => match pending.take()
+                .map(|r| r.and_then(|r| r.send(&req, response).map(|_| r)))
+            {
+                Some(Ok(upstream_response)) => {
+                    info!("{} {} {} [proxy]", upstream_response.status, req.method, req.path);
+                    if let Err(e) = scope.record(&req, &upstream_response) {
+                        error!("Error recording {} {}: {}", req.method, req.path, e);
+                    }
+                    None
+                }
+                Some(Err(e)) => {
+                    error!("502 {} {} [proxy]", req.method, req.path);
+                    error!("{}", &e);
+                    send_status(response, http_status::BadGateway, &e);
+                    None
+                }
+                None => Some(Responder::Proxying(pending, req)),
+            },
+            Responder::Sending(mut transfer, req, output) => {
+                if transfer.interval() != Duration::new(0, 0) {
+                    return Some(Responder::Sending(transfer, req, output));
+                }
+                match transfer.write_next(response) {
+                    Ok(false) => {
+                        output.lock().unwrap().waiting = true;
+                        Some(Responder::Sending(transfer, req, output))
+                    }
+                    Ok(true) => None,
+                    Err(e) => {
+                        // the connection is closed by the next timeout
+                        error!("Error sending {} {}: {}", req.method, req.path, e);
+                        Some(Responder::Abort)
+                    }
+                }
+            }
+            Responder::Done => None,
+            _ => Some(self),
+        }
     }
 }
@@ -1,11 +1,13 @@
 use std::io::{self, Write};
 use std::sync::mpsc::{SyncSender, SendError};
 
-pub struct Guard(SyncSender<()>);
+use super::socket;
+
+pub struct Guard(SyncSender<()>, Vec<String>);
 
 impl Guard {
-    pub fn new(tx: SyncSender<()>) -> Self {
-        Guard(tx)
+    pub fn new(tx: SyncSender<()>, addresses: Vec<String>) -> Self {
+        Guard(tx, addresses)
     }
 
     pub fn stop(self) -> Result<(), SendError<()>> {
@@ -19,5 +21,6 @@ impl Drop for Guard {
             writeln!(io::stderr(), "Error stopping server thread: {}", e)
                 .expect("Unable to write to stderr");
         }
+        socket::remove_unix_sockets(&self.1);
     }
 }
@@ -1,20 +1,28 @@
-use std::net::SocketAddr;
 use std::sync::mpsc::{self, Receiver, TryRecvError};
 use std::time::Duration;
 use std::thread;
 
-use rotor::{self, Scope};
-use rotor::mio::tcp::TcpListener;
+use rotor::{self, LoopInstance, Scope};
 use rotor::void::Void;
 
 use super::context::Context;
+#[cfg(feature = "tls")]
+use super::tls::{self, Tls};
 
+mod connection;
 mod engine;
 mod guard;
+mod socket;
 
 pub use self::engine::Responder;
+pub use self::socket::{Socket, UNIX_PREFIX};
 use self::engine::Fsm;
 use self::guard::Guard;
+use self::socket::Listener;
+
+#[cfg(not(feature = "tls"))]
+#[derive(Clone)]
+pub enum Tls {}
 
 fn shutdown_interval(scope: &mut Scope<Context>, rx: Receiver<()>)
 -> rotor::Response<Fsm, Void>
@@ -30,20 +38,69 @@ fn shutdown_interval(scope: &mut Scope<Context>, rx: Receiver<()>)
     })
 }
 
-pub fn start(context: Context, address: &str)
+fn bind(context: &Context) -> Result<Vec<(Listener, Option<Tls>)>, String> {
+    let mut listeners = Vec::new();
+    for listener in context.listeners() {
+        let tls = match listener.tls() {
+            Some(_) if listener.address().starts_with(UNIX_PREFIX) => {
+                return Err(format!("TLS is not supported on {}", listener.address()));
+            }
+            #[cfg(feature = "tls")]
+            Some(settings) => Some(try!(tls::acceptor(settings))),
+            #[cfg(not(feature = "tls"))]
+            Some(_) => {
+                return Err(format!("TLS is not supported on {}, responder was built without \
+                                    the tls feature", listener.address()));
+            }
+            None => None,
+        };
+        let lst = try!(Listener::bind(listener.address()));
+        listeners.push((lst, tls));
+    }
+    Ok(listeners)
+}
+
+fn addresses(context: &Context) -> Vec<String> {
+    context.listeners().iter().map(|l| l.address().to_owned()).collect()
+}
+
+fn add_listeners(loop_inst: &mut LoopInstance<Fsm>, listeners: Vec<(Listener, Option<Tls>)>)
+    -> Result<(), String>
+{
+    for (index, (lst, tls)) in listeners.into_iter().enumerate() {
+        try!(loop_inst.add_machine_with(|scope| {
+            self::engine::new_http(lst, index, tls, scope)
+        }).map_err(|e| format!("{}", e)));
+    }
+    Ok(())
+}
+
+pub fn start(mut context: Context, address: &str)
+    -> Result<Guard, String>
+{
+    context.set_address(address);
+    start_listeners(context)
+}
+
+pub fn run(mut context: Context, address: &str)
+    -> Result<(), String>
+{
+    context.set_address(address);
+    run_listeners(context)
+}
+
+pub fn start_listeners(context: Context)
     -> Result<Guard, String>
 {
     let (tx, rx) = mpsc::sync_channel::<()>(0);
 
+    let listeners = try!(bind(&context));
+    let addresses = addresses(&context);
+
     let event_loop = rotor::Loop::new(&rotor::Config::new()).unwrap();
     let mut loop_inst = event_loop.instantiate(context);
 
-    let address: SocketAddr = try!(address.parse().map_err(|e| format!("{}", e)));
-    let lst = try!(TcpListener::bind(&address).map_err(|e| format!("{}", e)));
-
-    try!(loop_inst.add_machine_with(|scope| {
-        self::engine::new_http(lst, (), scope)
-    }).map_err(|e| format!("{}", e)));
+    try!(add_listeners(&mut loop_inst, listeners));
 
     try!(loop_inst.add_machine_with(|scope| {
         shutdown_interval(scope, rx)
@@ -53,36 +110,101 @@ pub fn start(context: Context, address: &str)
         loop_inst.run().unwrap();
     });
 
-    Ok(Guard::new(tx))
+    Ok(Guard::new(tx, addresses))
 }
 
-pub fn run(context: Context, address: &str)
+pub fn run_listeners(context: Context)
     -> Result<(), String>
 {
+    let listeners = try!(bind(&context));
+    let addresses = addresses(&context);
+
     let event_loop = rotor::Loop::new(&rotor::Config::new()).unwrap();
     let mut loop_inst = event_loop.instantiate(context);
 
-    let address: SocketAddr = try!(address.parse().map_err(|e| format!("{}", e)));
-    let lst = try!(TcpListener::bind(&address).map_err(|e| format!("{}", e)));
-
-    try!(loop_inst.add_machine_with(|scope| {
-        self::engine::new_http(lst, (), scope)
-    }).map_err(|e| format!("{}", e)));
+    try!(add_listeners(&mut loop_inst, listeners));
 
-    try!(loop_inst.run().map_err(|e| format!("{}", e)));
-
-    Ok(())
+    let result = loop_inst.run().map_err(|e| format!("{}", e));
+    self::socket::remove_unix_sockets(&addresses);
+    result
 }
 
 #[cfg(test)]
 mod tests {
+    use std::env;
+    use std::io::{self, Read, Write};
+    use std::net::TcpStream;
+
+    use config::FaultKind;
     use context::Context;
+    use fault::Fault;
+    use handler::{Body, Handler};
     use super::start as start_server;
 
+    fn get(address: &str, path: &str) -> io::Result<Vec<u8>> {
+        let mut stream = try!(TcpStream::connect(address));
+        try!(write!(stream, "GET {} HTTP/1.1\r\nHost: localhost\r\n\r\n", path));
+        let mut data = Vec::new();
+        try!(stream.read_to_end(&mut data));
+        Ok(data)
+    }
+
     #[test]
     fn server_shutdown() {
         let context = Context::new();
         let guard = start_server(context, "127.0.0.1:7000").unwrap();
         guard.stop().unwrap();
     }
+
+    #[cfg(unix)]
+    #[test]
+    fn unix_socket_removed() {
+        let path = env::temp_dir().join("responder-test.sock");
+        let address = format!("unix:{}", path.display());
+
+        let guard = start_server(Context::new(), &address).unwrap();
+        assert!(start_server(Context::new(), &address).is_err());
+        guard.stop().unwrap();
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn faults_on_the_wire() {
+        let mut context = Context::new();
+        let faults = [
+            ("/reset", FaultKind::Reset),
+            ("/truncated", FaultKind::Truncated),
+            ("/malformed", FaultKind::MalformedChunk),
+            ("/garbage", FaultKind::Garbage(16)),
+        ];
+        for &(path, ref kind) in faults.iter() {
+            let mut handler = Handler::new(200);
+            handler.set_content(Some(Body::Data(b"hello world".to_vec())));
+            handler.set_fault(Some(Fault::new(kind.clone(), 1.0, None).unwrap()));
+            context.add_route(path, "GET".to_owned(), handler).unwrap();
+        }
+
+        let address = "127.0.0.1:7001";
+        let guard = start_server(context, address).unwrap();
+
+        match get(address, "/reset") {
+            Err(e) => assert_eq!(e.kind(), io::ErrorKind::ConnectionReset),
+            Ok(data) => panic!("unexpected response {:?}", String::from_utf8_lossy(&data)),
+        }
+
+        let data = String::from_utf8(get(address, "/truncated").unwrap()).unwrap();
+        assert!(data.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(data.contains("Content-Length: 11\r\n"));
+        assert!(data.ends_with("\r\n\r\nhello"));
+
+        let data = String::from_utf8(get(address, "/malformed").unwrap()).unwrap();
+        assert!(data.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(data.ends_with("Transfer-Encoding: chunked\r\n\r\n5\r\nhello\r\nzz"));
+
+        let data = get(address, "/garbage").unwrap();
+        assert!(data.starts_with(b"HTTP/1.1 200 OK\r\n"));
+        assert_eq!(data.len(), "HTTP/1.1 200 OK\r\n".len() + 16);
+
+        guard.stop().unwrap();
+    }
 }
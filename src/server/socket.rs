@@ -0,0 +1,263 @@
+use std::fs;
+use std::io::{self, Read, Write};
+use std::net::SocketAddr;
+
+#[cfg(feature = "tls")]
+use openssl::ssl::SslStream;
+use rotor::mio::{Evented, EventSet, PollOpt, Selector, Token, TryAccept};
+use rotor::mio::tcp::{TcpListener, TcpStream};
+#[cfg(unix)]
+use rotor::mio::unix::{UnixListener, UnixStream};
+use rotor_stream::SocketError;
+
+pub const UNIX_PREFIX: &'static str = "unix:";
+
+#[derive(Debug)]
+pub enum Listener {
+    Tcp(TcpListener),
+    #[cfg(unix)]
+    Unix(UnixListener),
+}
+
+impl Listener {
+    pub fn bind(address: &str) -> Result<Self, String> {
+        if address.starts_with(UNIX_PREFIX) {
+            return bind_unix(&address[UNIX_PREFIX.len()..]);
+        }
+        let address: SocketAddr = try!(address.parse()
+            .map_err(|e| format!("Invalid address {}: {}", address, e)));
+        TcpListener::bind(&address)
+            .map(Listener::Tcp)
+            .map_err(|e| format!("Cannot bind {}: {}", address, e))
+    }
+
+    fn evented(&self) -> &Evented {
+        match *self {
+            Listener::Tcp(ref lst) => lst,
+            #[cfg(unix)]
+            Listener::Unix(ref lst) => lst,
+        }
+    }
+}
+
+#[cfg(unix)]
+fn bind_unix(path: &str) -> Result<Listener, String> {
+    use std::os::unix::fs::FileTypeExt;
+    use std::os::unix::net;
+
+    if path.is_empty() {
+        return Err("Missing path of unix socket".to_owned());
+    }
+    if let Ok(metadata) = fs::symlink_metadata(path) {
+        if !metadata.file_type().is_socket() {
+            return Err(format!("Cannot bind {}: file exists and is not a socket", path));
+        }
+        if net::UnixStream::connect(path).is_ok() {
+            return Err(format!("Cannot bind {}: another server is listening on it", path));
+        }
+        try!(fs::remove_file(path).map_err(|e| format!("Cannot remove {}: {}", path, e)));
+    }
+    UnixListener::bind(path)
+        .map(Listener::Unix)
+        .map_err(|e| format!("Cannot bind {}: {}", path, e))
+}
+
+#[cfg(not(unix))]
+fn bind_unix(_path: &str) -> Result<Listener, String> {
+    Err("Unix sockets are not supported on this platform".to_owned())
+}
+
+pub fn remove_unix_sockets(addresses: &[String]) {
+    for address in addresses.iter().filter(|a| a.starts_with(UNIX_PREFIX)) {
+        let path = &address[UNIX_PREFIX.len()..];
+        if let Err(e) = fs::remove_file(path) {
+            warn!("Cannot remove {}: {}", path, e);
+        }
+    }
+}
+
+impl TryAccept for Listener {
+    type Output = Socket;
+
+    fn accept(&self) -> io::Result<Option<Socket>> {
+        match *self {
+            Listener::Tcp(ref lst) => {
+                lst.accept().map(|s| s.map(|(sock, _)| Socket::Tcp(sock)))
+            }
+            #[cfg(unix)]
+            Listener::Unix(ref lst) => lst.accept().map(|s| s.map(Socket::Unix)),
+        }
+    }
+}
+
+impl Evented for Listener {
+    fn register(&self, selector: &mut Selector, token: Token, interest: EventSet, opts: PollOpt)
+        -> io::Result<()>
+    {
+        self.evented().register(selector, token, interest, opts)
+    }
+
+    fn reregister(&self, selector: &mut Selector, token: Token, interest: EventSet,
+                  opts: PollOpt)
+        -> io::Result<()>
+    {
+        self.evented().reregister(selector, token, interest, opts)
+    }
+
+    fn deregister(&self, selector: &mut Selector) -> io::Result<()> {
+        self.evented().deregister(selector)
+    }
+}
+
+#[derive(Debug)]
+pub enum Socket {
+    Tcp(TcpStream),
+    #[cfg(unix)]
+    Unix(UnixStream),
+    #[cfg(feature = "tls")]
+    Tls(SslStream<TcpStream>),
+}
+
+impl Socket {
+    fn evented(&self) -> &Evented {
+        match *self {
+            Socket::Tcp(ref sock) => sock,
+            #[cfg(unix)]
+            Socket::Unix(ref sock) => sock,
+            #[cfg(feature = "tls")]
+            Socket::Tls(ref stream) => stream.get_ref(),
+        }
+    }
+
+    pub fn reset(&self) -> io::Result<()> {
+        match *self {
+            Socket::Tcp(ref sock) => reset(sock),
+            // unix domain sockets have no reset, closing them is enough
+            #[cfg(unix)]
+            Socket::Unix(_) => Ok(()),
+            #[cfg(feature = "tls")]
+            Socket::Tls(ref stream) => reset(stream.get_ref()),
+        }
+    }
+
+    pub fn peek(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match *self {
+            Socket::Tcp(ref sock) => peek(sock, buf),
+            #[cfg(unix)]
+            Socket::Unix(ref sock) => peek(sock, buf),
+            #[cfg(feature = "tls")]
+            Socket::Tls(ref mut stream) => stream.ssl_peek(buf).map_err(|e| {
+                e.into_io_error().unwrap_or_else(|e| io::Error::new(io::ErrorKind::Other, e))
+            }),
+        }
+    }
+}
+
+#[cfg(unix)]
+fn reset<S: ::std::os::unix::io::AsRawFd>(sock: &S) -> io::Result<()> {
+    use std::mem;
+    use libc;
+
+    let linger = libc::linger { l_onoff: 1, l_linger: 0 };
+    let result = unsafe {
+        libc::setsockopt(sock.as_raw_fd(), libc::SOL_SOCKET, libc::SO_LINGER,
+                         &linger as *const libc::linger as *const libc::c_void,
+                         mem::size_of::<libc::linger>() as libc::socklen_t)
+    };
+    if result < 0 {
+        Err(io::Error::last_os_error())
+    } else {
+        Ok(())
+    }
+}
+
+#[cfg(not(unix))]
+fn reset(_sock: &TcpStream) -> io::Result<()> {
+    Ok(())
+}
+
+#[cfg(unix)]
+fn peek<S: ::std::os::unix::io::AsRawFd>(sock: &S, buf: &mut [u8]) -> io::Result<usize> {
+    use libc;
+
+    let size = unsafe {
+        libc::recv(sock.as_raw_fd(), buf.as_mut_ptr() as *mut libc::c_void, buf.len(),
+                   libc::MSG_PEEK)
+    };
+    if size < 0 {
+        Err(io::Error::last_os_error())
+    } else {
+        Ok(size as usize)
+    }
+}
+
+/// Websocket routes are not available without `MSG_PEEK`
+#[cfg(not(unix))]
+fn peek(_sock: &TcpStream, _buf: &mut [u8]) -> io::Result<usize> {
+    Err(io::Error::new(io::ErrorKind::Other, "Cannot peek the socket"))
+}
+
+impl Read for Socket {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match *self {
+            Socket::Tcp(ref mut sock) => sock.read(buf),
+            #[cfg(unix)]
+            Socket::Unix(ref mut sock) => sock.read(buf),
+            #[cfg(feature = "tls")]
+            Socket::Tls(ref mut stream) => stream.read(buf),
+        }
+    }
+}
+
+impl Write for Socket {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match *self {
+            Socket::Tcp(ref mut sock) => sock.write(buf),
+            #[cfg(unix)]
+            Socket::Unix(ref mut sock) => sock.write(buf),
+            #[cfg(feature = "tls")]
+            Socket::Tls(ref mut stream) => stream.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match *self {
+            Socket::Tcp(ref mut sock) => sock.flush(),
+            #[cfg(unix)]
+            Socket::Unix(ref mut sock) => sock.flush(),
+            #[cfg(feature = "tls")]
+            Socket::Tls(ref mut stream) => stream.flush(),
+        }
+    }
+}
+
+impl Evented for Socket {
+    fn register(&self, selector: &mut Selector, token: Token, interest: EventSet, opts: PollOpt)
+        -> io::Result<()>
+    {
+        self.evented().register(selector, token, interest, opts)
+    }
+
+    fn reregister(&self, selector: &mut Selector, token: Token, interest: EventSet,
+                  opts: PollOpt)
+        -> io::Result<()>
+    {
+        self.evented().reregister(selector, token, interest, opts)
+    }
+
+    fn deregister(&self, selector: &mut Selector) -> io::Result<()> {
+        self.evented().deregister(selector)
+    }
+}
+
+impl SocketError for Socket {
+    fn take_socket_error(&self) -> io::Result<()> {
+        match *self {
+            Socket::Tcp(ref sock) => sock.take_socket_error(),
+            #[cfg(unix)]
+            Socket::Unix(ref sock) => sock.take_socket_error(),
+            #[cfg(feature = "tls")]
+            Socket::Tls(ref stream) => stream.get_ref().take_socket_error(),
+        }
+    }
+}
@@ -0,0 +1,93 @@
+use std::path::Path;
+use std::sync::Arc;
+
+use openssl::asn1::Asn1Time;
+use openssl::bn::{BigNum, MsbOption};
+use openssl::error::ErrorStack;
+use openssl::hash::MessageDigest;
+use openssl::pkey::{PKey, Private};
+use openssl::rsa::Rsa;
+use openssl::ssl::{SslAcceptor, SslAcceptorBuilder, SslFiletype, SslMethod, SslVerifyMode};
+use openssl::x509::{X509, X509Name};
+use openssl::x509::extension::{BasicConstraints, ExtendedKeyUsage, SubjectAlternativeName};
+
+use super::config;
+
+const SELF_SIGNED_DAYS: u32 = 365;
+
+pub type Tls = Arc<SslAcceptor>;
+
+pub fn acceptor(settings: &config::Tls) -> Result<Tls, String> {
+    let mut builder = try!(SslAcceptor::mozilla_intermediate_v5(SslMethod::tls())
+        .map_err(|e| format!("Cannot initialize TLS: {}", e)));
+
+    match (&settings.cert, &settings.key) {
+        (&Some(ref cert), &Some(ref key)) => {
+            try!(builder.set_certificate_chain_file(cert)
+                .map_err(|e| format!("Cannot read certificate {}: {}", cert.display(), e)));
+            try!(builder.set_private_key_file(key, SslFiletype::PEM)
+                .map_err(|e| format!("Cannot read private key {}: {}", key.display(), e)));
+            try!(builder.check_private_key()
+                .map_err(|e| format!("Private key {} does not match certificate {}: {}",
+                                     key.display(), cert.display(), e)));
+        }
+        (&None, &None) => {
+            let (cert, key) = try!(self_signed()
+                .map_err(|e| format!("Cannot generate certificate: {}", e)));
+            try!(builder.set_certificate(&cert).map_err(|e| format!("{}", e)));
+            try!(builder.set_private_key(&key).map_err(|e| format!("{}", e)));
+        }
+        _ => return Err("TLS settings need both cert and key, or none of them".to_owned()),
+    }
+
+    if let Some(ref client_ca) = settings.client_ca {
+        try!(verify_clients(&mut builder, client_ca)
+            .map_err(|e| format!("Cannot read client CA {}: {}", client_ca.display(), e)));
+    }
+
+    Ok(Arc::new(builder.build()))
+}
+
+fn verify_clients(builder: &mut SslAcceptorBuilder, client_ca: &Path)
+    -> Result<(), ErrorStack>
+{
+    try!(builder.set_ca_file(client_ca));
+    builder.set_client_ca_list(try!(X509Name::load_client_ca_file(client_ca)));
+    builder.set_verify(SslVerifyMode::PEER | SslVerifyMode::FAIL_IF_NO_PEER_CERT);
+    Ok(())
+}
+
+fn self_signed() -> Result<(X509, PKey<Private>), ErrorStack> {
+    let key = try!(PKey::from_rsa(try!(Rsa::generate(2048))));
+
+    let mut name = try!(X509Name::builder());
+    try!(name.append_entry_by_text("CN", "localhost"));
+    try!(name.append_entry_by_text("O", "responder"));
+    let name = name.build();
+
+    let mut serial = try!(BigNum::new());
+    try!(serial.rand(128, MsbOption::MAYBE_ZERO, false));
+
+    let mut cert = try!(X509::builder());
+    try!(cert.set_version(2));
+    try!(cert.set_serial_number(&*try!(serial.to_asn1_integer())));
+    try!(cert.set_subject_name(&name));
+    try!(cert.set_issuer_name(&name));
+    try!(cert.set_pubkey(&key));
+    try!(cert.set_not_before(&*try!(Asn1Time::days_from_now(0))));
+    try!(cert.set_not_after(&*try!(Asn1Time::days_from_now(SELF_SIGNED_DAYS))));
+
+    let basic_constraints = try!(BasicConstraints::new().critical().build());
+    try!(cert.append_extension(basic_constraints));
+    let key_usage = try!(ExtendedKeyUsage::new().server_auth().build());
+    try!(cert.append_extension(key_usage));
+    let alt_names = try!(SubjectAlternativeName::new()
+        .dns("localhost")
+        .ip("127.0.0.1")
+        .ip("::1")
+        .build(&cert.x509v3_context(None, None)));
+    try!(cert.append_extension(alt_names));
+
+    try!(cert.sign(&key, MessageDigest::sha256()));
+    Ok((cert.build(), key))
+}
@@ -0,0 +1,133 @@
+use std::borrow::Cow;
+use std::cmp;
+use std::collections::VecDeque;
+use std::fmt;
+use std::io::Read;
+use std::time::{Duration, Instant};
+
+use rotor_http::server::Response;
+
+use super::fake::Fake;
+
+pub const INTERVAL_MS: u64 = 100;
+
+pub const BLOCK_SIZE: u64 = 64 * 1024;
+
+#[derive(Clone, Debug)]
+pub struct Chunk {
+    pub data: Vec<u8>,
+    pub fake: Option<Fake>,
+    pub delay: Duration,
+}
+
+impl Chunk {
+    pub fn body<'a>(&'a self) -> Cow<'a, [u8]> {
+        match self.fake {
+            Some(ref fake) => Cow::Owned(fake.body()),
+            None => Cow::Borrowed(&self.data),
+        }
+    }
+}
+
+enum Source {
+    Stream {
+        reader: Box<Read + Send>,
+        remaining: u64,
+        rate: Option<u64>,
+        sent: u64,
+        started: Instant,
+    },
+    Chunks(VecDeque<Chunk>, bool),
+}
+
+pub struct Transfer {
+    source: Source,
+}
+
+impl Transfer {
+    pub fn new<R: Read + Send + 'static>(source: R, length: u64, rate: Option<u64>) -> Self {
+        Transfer {
+            source: Source::Stream {
+                reader: Box::new(source),
+                remaining: length,
+                rate: rate,
+                sent: 0,
+                started: Instant::now(),
+            },
+        }
+    }
+
+    pub fn chunks(chunks: Vec<Chunk>, repeat: bool) -> Self {
+        Transfer {
+            source: Source::Chunks(chunks.into_iter().collect(), repeat),
+        }
+    }
+
+    pub fn interval(&self) -> Duration {
+        match self.source {
+            Source::Stream { rate, .. } => interval(rate),
+            Source::Chunks(ref chunks, _) => {
+                chunks.front().map(|c| c.delay).unwrap_or(Duration::new(0, 0))
+            }
+        }
+    }
+
+    pub fn write_next(&mut self, res: &mut Response) -> Result<bool, String> {
+        let finished = match self.source {
+            Source::Stream { ref mut reader, ref mut remaining, rate, ref mut sent, started } => {
+                let slice = match rate {
+                    // the allowance follows the time elapsed since the start,
+                    // so late timeouts and rates below one byte per interval
+                    // do not change the average rate
+                    Some(rate) => {
+                        let elapsed = started.elapsed();
+                        let millis = elapsed.as_secs() * 1000 +
+                            (elapsed.subsec_nanos() / 1000000) as u64;
+                        (rate * millis / 1000).saturating_sub(*sent)
+                    }
+                    None => BLOCK_SIZE,
+                };
+                let mut buf = vec![0u8; cmp::min(slice, *remaining) as usize];
+                try!(reader.read_exact(&mut buf).map_err(|e| format!("{}", e)));
+                res.write_body(&buf);
+                *remaining -= buf.len() as u64;
+                *sent += buf.len() as u64;
+                *remaining == 0
+            }
+            Source::Chunks(ref mut chunks, repeat) => {
+                if let Some(chunk) = chunks.pop_front() {
+                    res.write_body(&chunk.body());
+                    if repeat {
+                        chunks.push_back(chunk);
+                    }
+                }
+                chunks.is_empty()
+            }
+        };
+
+        if finished {
+            res.done();
+        }
+        Ok(finished)
+    }
+}
+
+impl fmt::Debug for Transfer {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.source {
+            Source::Stream { remaining, rate, .. } => {
+                write!(f, "Transfer {{ remaining: {}, rate: {:?} }}", remaining, rate)
+            }
+            Source::Chunks(ref chunks, repeat) => {
+                write!(f, "Transfer {{ chunks: {}, repeat: {} }}", chunks.len(), repeat)
+            }
+        }
+    }
+}
+
+pub fn interval(rate: Option<u64>) -> Duration {
+    match rate {
+        Some(_) => Duration::from_millis(INTERVAL_MS),
+        None => Duration::new(0, 0),
+    }
+}
@@ -0,0 +1,550 @@
+use std::collections::VecDeque;
+use std::error::Error;
+use std::str;
+use std::sync::Arc;
+use std::time::Duration;
+
+use httparse;
+use regex::Regex;
+use rotor::{Scope, Time};
+use rotor_stream::{Exception, Intent, Protocol, Transport};
+use rustc_serialize::base64::{ToBase64, STANDARD};
+use rustc_serialize::json::Json;
+use sha1::Sha1;
+
+use super::context::Context;
+use super::fake::Fake;
+use super::server::Socket;
+
+const GUID: &'static str = "258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
+
+const VERSION: &'static str = "13";
+
+pub const MAX_HEAD: usize = 8192;
+
+const MAX_MESSAGE: usize = 16 * 1024 * 1024;
+
+const TIMEOUT: u64 = 10;
+
+pub const CLOSE_NORMAL: u16 = 1000;
+pub const CLOSE_PROTOCOL_ERROR: u16 = 1002;
+pub const CLOSE_INVALID_DATA: u16 = 1007;
+
+pub fn valid_close_code(code: u16) -> bool {
+    match code {
+        1000...1003 | 1007...1014 | 3000...4999 => true,
+        _ => false,
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Opcode {
+    Continuation,
+    Text,
+    Binary,
+    Close,
+    Ping,
+    Pong,
+}
+
+impl Opcode {
+    fn from_u8(code: u8) -> Option<Self> {
+        match code {
+            0x0 => Some(Opcode::Continuation),
+            0x1 => Some(Opcode::Text),
+            0x2 => Some(Opcode::Binary),
+            0x8 => Some(Opcode::Close),
+            0x9 => Some(Opcode::Ping),
+            0xA => Some(Opcode::Pong),
+            _ => None,
+        }
+    }
+
+    fn code(&self) -> u8 {
+        match *self {
+            Opcode::Continuation => 0x0,
+            Opcode::Text => 0x1,
+            Opcode::Binary => 0x2,
+            Opcode::Close => 0x8,
+            Opcode::Ping => 0x9,
+            Opcode::Pong => 0xA,
+        }
+    }
+
+    fn is_control(&self) -> bool {
+        self.code() & 0x8 != 0
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub struct Frame {
+    pub fin: bool,
+    pub opcode: Opcode,
+    pub payload: Vec<u8>,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum Parsed {
+    Frame(Frame, usize),
+    Incomplete(usize),
+}
+
+pub fn parse_frame(data: &[u8]) -> Result<Parsed, String> {
+    if data.len() < 2 {
+        return Ok(Parsed::Incomplete(2));
+    }
+
+    let fin = data[0] & 0x80 != 0;
+    if data[0] & 0x70 != 0 {
+        return Err("Reserved bits are set".to_owned());
+    }
+    let opcode = try!(Opcode::from_u8(data[0] & 0x0F)
+        .ok_or(format!("Unknown opcode {:#x}", data[0] & 0x0F)));
+
+    let (length, mut offset) = match data[1] & 0x7F {
+        126 => {
+            if data.len() < 4 {
+                return Ok(Parsed::Incomplete(4));
+            }
+            (data[2..4].iter().fold(0, |n, &b| n << 8 | b as u64), 4)
+        }
+        127 => {
+            if data.len() < 10 {
+                return Ok(Parsed::Incomplete(10));
+            }
+            (data[2..10].iter().fold(0, |n, &b| n << 8 | b as u64), 10)
+        }
+        length => (length as u64, 2),
+    };
+
+    if opcode.is_control() && (!fin || length > 125) {
+        return Err("Control frames cannot be fragmented or longer than 125 bytes".to_owned());
+    }
+    if length > MAX_MESSAGE as u64 {
+        return Err(format!("Frame of {} bytes is too long", length));
+    }
+
+    if data[1] & 0x80 == 0 {
+        return Err("Client frames must be masked".to_owned());
+    }
+    if data.len() < offset + 4 {
+        return Ok(Parsed::Incomplete(offset + 4));
+    }
+    let mut mask = [0u8; 4];
+    mask.copy_from_slice(&data[offset..offset + 4]);
+    offset += 4;
+
+    let end = offset + length as usize;
+    if data.len() < end {
+        return Ok(Parsed::Incomplete(end));
+    }
+
+    let payload = data[offset..end].iter()
+        .enumerate()
+        .map(|(i, b)| b ^ mask[i % 4])
+        .collect();
+
+    Ok(Parsed::Frame(Frame { fin: fin, opcode: opcode, payload: payload }, end))
+}
+
+pub fn encode_frame(opcode: Opcode, payload: &[u8]) -> Vec<u8> {
+    let mut frame = Vec::with_capacity(payload.len() + 10);
+    frame.push(0x80 | opcode.code());
+    let length = payload.len() as u64;
+    if length < 126 {
+        frame.push(length as u8);
+    } else if length <= 0xFFFF {
+        frame.push(126);
+        frame.extend((0..2).rev().map(|i| (length >> (i * 8)) as u8));
+    } else {
+        frame.push(127);
+        frame.extend((0..8).rev().map(|i| (length >> (i * 8)) as u8));
+    }
+    frame.extend_from_slice(payload);
+    frame
+}
+
+fn close_frame(code: u16, reason: &str) -> Vec<u8> {
+    let mut payload = vec![(code >> 8) as u8, code as u8];
+    payload.extend_from_slice(reason.as_bytes());
+    payload.truncate(125);
+    encode_frame(Opcode::Close, &payload)
+}
+
+pub fn accept_key(key: &str) -> String {
+    let mut sha1 = Sha1::new();
+    sha1.update(key.trim().as_bytes());
+    sha1.update(GUID.as_bytes());
+    sha1.digest().bytes().to_base64(STANDARD)
+}
+
+#[derive(Debug)]
+pub struct Message {
+    pub opcode: Opcode,
+    pub data: Vec<u8>,
+    pub fake: Option<Fake>,
+    pub delay: Duration,
+}
+
+#[derive(Debug)]
+pub struct Close {
+    pub code: u16,
+    pub reason: String,
+    pub delay: Duration,
+}
+
+#[derive(Debug)]
+pub struct Reply {
+    pub pattern: Option<Regex>,
+    pub json: Option<Json>,
+    pub messages: Vec<Message>,
+    pub close: Option<Close>,
+}
+
+impl Reply {
+    fn matches(&self, data: &[u8]) -> bool {
+        let text = str::from_utf8(data).ok();
+        if let Some(ref pattern) = self.pattern {
+            if !text.map_or(false, |text| pattern.is_match(text)) {
+                return false;
+            }
+        }
+        if let Some(ref expected) = self.json {
+            let json = text.and_then(|text| Json::from_str(text).ok());
+            if !json.map_or(false, |json| contains(&json, expected)) {
+                return false;
+            }
+        }
+        true
+    }
+}
+
+fn contains(value: &Json, expected: &Json) -> bool {
+    match (value, expected) {
+        (&Json::Object(ref value), &Json::Object(ref expected)) => {
+            expected.iter().all(|(key, e)| value.get(key).map_or(false, |v| contains(v, e)))
+        }
+        _ => value == expected,
+    }
+}
+
+#[derive(Debug)]
+pub struct WebSocket {
+    pub messages: Vec<Message>,
+    pub replies: Vec<Reply>,
+    pub close: Option<Close>,
+}
+
+#[derive(Debug)]
+enum Action {
+    Send(Vec<u8>),
+    Close(u16, String),
+}
+
+#[derive(Debug, PartialEq)]
+enum State {
+    Handshake,
+    Open,
+    Closing,
+}
+
+pub struct Session {
+    websocket: Arc<WebSocket>,
+    state: State,
+    queue: VecDeque<(Time, Action)>,
+    message: Option<(Opcode, Vec<u8>)>,
+}
+
+impl Session {
+    fn handshake(mut self, transport: &mut Transport<Socket>, end: usize,
+        scope: &mut Scope<Context>)
+        -> Intent<Self>
+    {
+        let (path, key, version) = {
+            let mut headers = [httparse::EMPTY_HEADER; 64];
+            let mut req = httparse::Request::new(&mut headers);
+            let _ = req.parse(&transport.input()[..end + 4]);
+            let header = |name: &str| req.headers.iter()
+                .find(|h| h.name.eq_ignore_ascii_case(name))
+                .and_then(|h| str::from_utf8(h.value).ok())
+                .map(|value| value.trim().to_owned());
+            (req.path.unwrap_or("").to_owned(),
+             header("Sec-WebSocket-Key"),
+             header("Sec-WebSocket-Version"))
+        };
+        transport.input().consume(end + 4);
+
+        if version.as_ref().map(|v| &v[..]) != Some(VERSION) {
+            warn!("426 GET {} [websocket: unsupported version {:?}]", path, version);
+            transport.output().extend(format!(
+                "HTTP/1.1 426 Upgrade Required\r\n\
+                 Sec-WebSocket-Version: {}\r\n\
+                 Content-Length: 0\r\n\
+                 Connection: close\r\n\r\n", VERSION).as_bytes());
+            return self.closing(scope);
+        }
+
+        let key = match key {
+            Some(key) => key,
+            None => {
+                warn!("400 GET {} [websocket: missing Sec-WebSocket-Key]", path);
+                transport.output().extend(
+                    b"HTTP/1.1 400 Bad Request\r\nContent-Length: 0\r\nConnection: close\r\n\r\n");
+                return self.closing(scope);
+            }
+        };
+
+        info!("101 GET {}", path);
+        transport.output().extend(format!(
+            "HTTP/1.1 101 Switching Protocols\r\n\
+             Upgrade: websocket\r\n\
+             Connection: Upgrade\r\n\
+             Sec-WebSocket-Accept: {}\r\n\r\n", accept_key(&key)).as_bytes());
+
+        let websocket = self.websocket.clone();
+        self.schedule(&websocket.messages, websocket.close.as_ref(), scope.now());
+        self.state = State::Open;
+        self.receive(transport, scope)
+    }
+
+    fn receive(mut self, transport: &mut Transport<Socket>, scope: &mut Scope<Context>)
+        -> Intent<Self>
+    {
+        loop {
+            let parsed = parse_frame(&transport.input()[..]);
+            let frame = match parsed {
+                Ok(Parsed::Frame(frame, size)) => {
+                    transport.input().consume(size);
+                    frame
+                }
+                Ok(Parsed::Incomplete(size)) => {
+                    let now = scope.now();
+                    while self.queue.front().map_or(false, |&(time, _)| time <= now) {
+                        match self.queue.pop_front().unwrap().1 {
+                            Action::Send(frame) => transport.output().extend(&frame),
+                            Action::Close(code, reason) => {
+                                return self.close(transport, code, &reason, scope);
+                            }
+                        }
+                    }
+                    let deadline = self.queue.front().map(|&(time, _)| time);
+                    return Intent::of(self).expect_bytes(size).deadline_opt(deadline);
+                }
+                Err(e) => {
+                    warn!("Invalid websocket frame: {}", e);
+                    return self.close(transport, CLOSE_PROTOCOL_ERROR, &e, scope);
+                }
+            };
+
+            match frame.opcode {
+                Opcode::Ping => transport.output().extend(&encode_frame(Opcode::Pong, &frame.payload)),
+                Opcode::Pong => {}
+                Opcode::Close => {
+                    let code = match frame.payload.len() {
+                        0 => CLOSE_NORMAL,
+                        1 => CLOSE_PROTOCOL_ERROR,
+                        _ => (frame.payload[0] as u16) << 8 | frame.payload[1] as u16,
+                    };
+                    if !valid_close_code(code) {
+                        warn!("Invalid websocket close code {}", code);
+                        return self.close(transport, CLOSE_PROTOCOL_ERROR, "Invalid close code",
+                                          scope);
+                    }
+                    if frame.payload.len() > 2 && str::from_utf8(&frame.payload[2..]).is_err() {
+                        warn!("Invalid websocket close reason");
+                        return self.close(transport, CLOSE_INVALID_DATA, "Invalid utf-8", scope);
+                    }
+                    return self.close(transport, code, "", scope);
+                }
+                Opcode::Text | Opcode::Binary | Opcode::Continuation => {
+                    match self.assemble(frame) {
+                        Ok(Some((Opcode::Text, ref data))) if str::from_utf8(data).is_err() => {
+                            warn!("Invalid websocket message: text is not valid utf-8");
+                            return self.close(transport, CLOSE_INVALID_DATA, "Invalid utf-8",
+                                              scope);
+                        }
+                        Ok(Some((_, data))) => self.reply(&data, scope.now()),
+                        Ok(None) => {}
+                        Err(e) => {
+                            warn!("Invalid websocket message: {}", e);
+                            return self.close(transport, CLOSE_PROTOCOL_ERROR, &e, scope);
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    fn assemble(&mut self, frame: Frame) -> Result<Option<(Opcode, Vec<u8>)>, String> {
+        let (opcode, data) = match (frame.opcode, self.message.take()) {
+            (Opcode::Continuation, Some((opcode, mut data))) => {
+                data.extend(frame.payload);
+                (opcode, data)
+            }
+            (Opcode::Continuation, None) => return Err("Unexpected continuation frame".to_owned()),
+            (_, Some(_)) => return Err("Expected a continuation frame".to_owned()),
+            (opcode, None) => (opcode, frame.payload),
+        };
+
+        if data.len() > MAX_MESSAGE {
+            return Err(format!("Message of {} bytes is too long", data.len()));
+        }
+        if frame.fin {
+            debug!("Websocket message received: {:?} of {} bytes", opcode, data.len());
+            Ok(Some((opcode, data)))
+        } else {
+            self.message = Some((opcode, data));
+            Ok(None)
+        }
+    }
+
+    fn reply(&mut self, data: &[u8], now: Time) {
+        let websocket = self.websocket.clone();
+        if let Some(reply) = websocket.replies.iter().find(|r| r.matches(data)) {
+            self.schedule(&reply.messages, reply.close.as_ref(), now);
+        }
+    }
+
+    fn schedule(&mut self, messages: &[Message], close: Option<&Close>, now: Time) {
+        let mut time = now;
+        for message in messages.iter() {
+            time = time + message.delay;
+            let data = match message.fake {
+                Some(ref fake) => fake.body(),
+                None => message.data.clone(),
+            };
+            self.enqueue(time, Action::Send(encode_frame(message.opcode, &data)));
+        }
+        if let Some(close) = close {
+            time = time + close.delay;
+            self.enqueue(time, Action::Close(close.code, close.reason.clone()));
+        }
+    }
+
+    fn enqueue(&mut self, time: Time, action: Action) {
+        let index = self.queue.iter().position(|&(t, _)| t > time).unwrap_or(self.queue.len());
+        self.queue.insert(index, (time, action));
+    }
+
+    fn close(mut self, transport: &mut Transport<Socket>, code: u16, reason: &str,
+        scope: &mut Scope<Context>)
+        -> Intent<Self>
+    {
+        debug!("Closing websocket: {} {}", code, reason);
+        transport.output().extend(&close_frame(code, reason));
+        self.queue.clear();
+        self.closing(scope)
+    }
+
+    fn closing(mut self, scope: &mut Scope<Context>) -> Intent<Self> {
+        self.state = State::Closing;
+        Intent::of(self).expect_flush().deadline(scope.now() + Duration::new(TIMEOUT, 0))
+    }
+}
+
+impl Protocol for Session {
+    type Context = Context;
+    type Socket = Socket;
+    type Seed = Arc<WebSocket>;
+
+    fn create(seed: Self::Seed, _sock: &mut Socket, scope: &mut Scope<Context>)
+        -> Intent<Self>
+    {
+        let session = Session {
+            websocket: seed,
+            state: State::Handshake,
+            queue: VecDeque::new(),
+            message: None,
+        };
+        Intent::of(session)
+            .expect_delimiter(b"\r\n\r\n", MAX_HEAD)
+            .deadline(scope.now() + Duration::new(TIMEOUT, 0))
+    }
+
+    fn bytes_read(self, transport: &mut Transport<Socket>, end: usize,
+        scope: &mut Scope<Context>)
+        -> Intent<Self>
+    {
+        match self.state {
+            State::Handshake => self.handshake(transport, end, scope),
+            State::Open => self.receive(transport, scope),
+            State::Closing => Intent::of(self).expect_flush(),
+        }
+    }
+
+    fn bytes_flushed(self, _transport: &mut Transport<Socket>, _scope: &mut Scope<Context>)
+        -> Intent<Self>
+    {
+        Intent::done()
+    }
+
+    fn timeout(self, transport: &mut Transport<Socket>, scope: &mut Scope<Context>)
+        -> Intent<Self>
+    {
+        match self.state {
+            State::Open => self.receive(transport, scope),
+            State::Handshake | State::Closing => Intent::done(),
+        }
+    }
+
+    fn exception(self, _transport: &mut Transport<Socket>, reason: Exception,
+        _scope: &mut Scope<Context>)
+        -> Intent<Self>
+    {
+        debug!("Websocket connection closed: {}", reason);
+        Intent::done()
+    }
+
+    fn fatal(self, reason: Exception, _scope: &mut Scope<Context>) -> Option<Box<Error>> {
+        debug!("Websocket connection closed: {}", reason);
+        None
+    }
+
+    fn wakeup(self, transport: &mut Transport<Socket>, scope: &mut Scope<Context>)
+        -> Intent<Self>
+    {
+        match self.state {
+            State::Open => self.receive(transport, scope),
+            State::Handshake | State::Closing => Intent::of(self).sleep(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{accept_key, encode_frame, parse_frame, valid_close_code, Frame, Opcode, Parsed};
+
+    #[test]
+    fn handshake_accept_key() {
+        assert_eq!(accept_key("dGhlIHNhbXBsZSBub25jZQ=="), "s3pPLMBiTxaQ9kYGzzhZRbK+xOo=");
+    }
+
+    #[test]
+    fn parse_masked_frame() {
+        let data = [0x81, 0x85, 0x37, 0xfa, 0x21, 0x3d, 0x7f, 0x9f, 0x4d, 0x51, 0x58];
+        let frame = Frame { fin: true, opcode: Opcode::Text, payload: b"Hello".to_vec() };
+        assert_eq!(parse_frame(&data).unwrap(), Parsed::Frame(frame, 11));
+        assert_eq!(parse_frame(&data[..1]).unwrap(), Parsed::Incomplete(2));
+        assert_eq!(parse_frame(&data[..4]).unwrap(), Parsed::Incomplete(6));
+        assert_eq!(parse_frame(&data[..8]).unwrap(), Parsed::Incomplete(11));
+        assert!(parse_frame(&[0x89, 0xfe, 0x00, 0x7e]).is_err());
+        assert!(parse_frame(&[0x81, 0x05, b'H', b'e', b'l', b'l', b'o']).is_err());
+
+        let payload = vec![b'x'; 300];
+        let encoded = encode_frame(Opcode::Binary, &payload);
+        assert_eq!(&encoded[..4], &[0x82, 0x7e, 0x01, 0x2c]);
+    }
+
+    #[test]
+    fn close_codes() {
+        assert!(valid_close_code(1000));
+        assert!(valid_close_code(1011));
+        assert!(valid_close_code(4999));
+        assert!(!valid_close_code(999));
+        assert!(!valid_close_code(1005));
+        assert!(!valid_close_code(1006));
+        assert!(!valid_close_code(1015));
+        assert!(!valid_close_code(2000));
+        assert!(!valid_close_code(5000));
+    }
+}